@@ -1,43 +1,338 @@
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+};
 
-use log::error;
+use anyhow::{Result, anyhow};
+use globset::{Glob, GlobMatcher};
+use log::{error, info};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+use crate::markup::{Style, Styles, WindowState, escape};
+use crate::wm::WindowInfo;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct SwayNameManagerConfig {
+    /// Exact app name to symbol mapping. Checked before `rules`
     pub app_symbols: HashMap<String, String>,
-    /// If set this wraps any fullscreen applications on hyprland in a <span foreground={color}>
+    /// Ordered list of pattern rules. The first matching rule wins
+    pub rules: Vec<SymbolRule>,
+    /// If set this wraps any fullscreen applications in a <span foreground={color}>. Shorthand
+    /// for the foreground of `styles.fullscreen`
     pub fullscreen_color: Option<String>,
+    /// Styles of the symbols by application and window state
+    pub styles: Styles,
+    /// Names are Pango markup as soon as a style is set. This makes them markup without styles
+    pub markup: bool,
+    /// Template of the workspace name. `{num}` is replaced with the workspace number, `{label}`
+    /// with the rendered `label_format` and `{icons}` with the symbols of the windows
+    pub format: String,
+    /// Template of the name of a workspace without windows. Supports `{num}` and `{label}`
+    pub empty_format: String,
+    /// Template of the user given label of a workspace. Supports `{label}`. Only rendered if the
+    /// workspace has a label
+    pub label_format: String,
+    /// Put between the symbols
+    pub separator: String,
+    /// Put in front of every symbol
+    pub prefix: String,
+    /// Put after every symbol
+    pub suffix: String,
+    /// If set, repeated symbols of a workspace are shown once with their count
+    pub dedup: Option<DedupConfig>,
+    /// What to show for windows without a matching symbol
+    pub fallback: Fallback,
+    /// Events arriving within this many milliseconds after the first one are handled together
+    pub debounce_ms: u64,
+}
+
+/// Policy for windows without a matching symbol
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Fallback {
+    /// The name of the window as is
+    #[default]
+    Name,
+    /// A fixed symbol
+    Symbol { symbol: String },
+    /// The name of the window cut to `length` characters
+    Truncate { length: usize },
+    /// The last segment of a reverse-DNS id, e.g. `Nautilus` for `org.gnome.Nautilus`
+    LastSegment,
+    /// Do not show the window at all
+    Hide,
+}
+
+impl Fallback {
+    /// Returns the symbol for an unmatched window with the given name or `None` if it should be
+    /// hidden
+    pub fn apply(&self, name: Option<&str>) -> Option<String> {
+        match (self, name) {
+            (Fallback::Hide, _) => None,
+            (Fallback::Symbol { symbol }, _) => Some(symbol.clone()),
+            (_, None) => Some("*invalid*".to_string()),
+            (Fallback::Name, Some(name)) => Some(name.to_string()),
+            (Fallback::Truncate { length }, Some(name)) => {
+                Some(name.chars().take(*length).collect())
+            }
+            (Fallback::LastSegment, Some(name)) => name.rsplit('.').next().map(str::to_string),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct DedupConfig {
+    /// Symbols occurring at least this many times are collapsed
+    pub threshold: usize,
+    /// Template of a collapsed symbol. Supports `{symbol}`, `{count}` and `{count_superscript}`
+    pub format: String,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 2,
+            format: "{symbol}×{count}".to_string(),
+        }
+    }
+}
+
+impl Default for SwayNameManagerConfig {
+    fn default() -> Self {
+        Self {
+            app_symbols: HashMap::new(),
+            rules: vec![],
+            fullscreen_color: None,
+            styles: Styles::default(),
+            markup: false,
+            format: "{num}{label}: {icons}".to_string(),
+            empty_format: "{num}{label}".to_string(),
+            label_format: ":{label}".to_string(),
+            separator: "|".to_string(),
+            prefix: String::new(),
+            suffix: String::new(),
+            dedup: None,
+            fallback: Fallback::default(),
+            debounce_ms: 30,
+        }
+    }
+}
+
+/// How the patterns of a [`SymbolRule`] are interpreted
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PatternKind {
+    /// Regular expression. Not anchored, so use `^` and `$` to match the whole name
+    #[default]
+    Regex,
+    /// Shell style glob matching the whole name
+    Glob,
+    /// The name has to be equal to the pattern
+    Exact,
+}
+
+/// Serialized form of a [`SymbolRule`]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct SymbolRuleDef {
+    /// Matches any of the names of [`WindowInfo::names`]
+    #[serde(rename = "match", default, skip_serializing_if = "Option::is_none")]
+    any: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    app_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    instance: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    initial_title: Option<String>,
+    #[serde(default)]
+    kind: PatternKind,
+    symbol: String,
+}
+
+/// A rule mapping every window matching all of its patterns to `symbol`. The patterns are
+/// compiled once while loading the config
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(try_from = "SymbolRuleDef", into = "SymbolRuleDef")]
+pub struct SymbolRule {
+    pub symbol: String,
+    def: SymbolRuleDef,
+    matchers: Vec<(WindowField, Matcher)>,
+}
+
+/// The window property a pattern is matched against
+#[derive(Debug, Clone, Copy)]
+enum WindowField {
+    Any,
+    AppId,
+    Class,
+    Instance,
+    Title,
+    InitialTitle,
+}
+
+impl WindowField {
+    fn is_match(&self, window: &WindowInfo, matcher: &Matcher) -> bool {
+        let value = match self {
+            WindowField::Any => {
+                return window.names().iter().any(|name| matcher.is_match(name));
+            }
+            WindowField::AppId => &window.app_id,
+            WindowField::Class => &window.class,
+            WindowField::Instance => &window.instance,
+            WindowField::Title => &window.title,
+            WindowField::InitialTitle => &window.initial_title,
+        };
+        value
+            .as_deref()
+            .is_some_and(|value| matcher.is_match(value))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Regex(Regex),
+    Glob(GlobMatcher),
+    Exact(String),
+}
+
+impl Matcher {
+    fn new(pattern: &str, kind: PatternKind) -> Result<Self> {
+        Ok(match kind {
+            PatternKind::Regex => Matcher::Regex(Regex::new(pattern)?),
+            PatternKind::Glob => Matcher::Glob(Glob::new(pattern)?.compile_matcher()),
+            PatternKind::Exact => Matcher::Exact(pattern.to_string()),
+        })
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        match self {
+            Matcher::Regex(regex) => regex.is_match(name),
+            Matcher::Glob(glob) => glob.is_match(name),
+            Matcher::Exact(exact) => exact == name,
+        }
+    }
+}
+
+impl TryFrom<SymbolRuleDef> for SymbolRule {
+    type Error = anyhow::Error;
+
+    fn try_from(def: SymbolRuleDef) -> Result<Self> {
+        let matchers = [
+            (WindowField::Any, &def.any),
+            (WindowField::AppId, &def.app_id),
+            (WindowField::Class, &def.class),
+            (WindowField::Instance, &def.instance),
+            (WindowField::Title, &def.title),
+            (WindowField::InitialTitle, &def.initial_title),
+        ]
+        .into_iter()
+        .filter_map(|(field, pattern)| Some((field, pattern.as_deref()?)))
+        .map(|(field, pattern)| Ok((field, Matcher::new(pattern, def.kind)?)))
+        .collect::<Result<Vec<_>>>()?;
+        if matchers.is_empty() {
+            return Err(anyhow!("Rule for symbol {} has no pattern", def.symbol));
+        }
+        Ok(Self {
+            symbol: def.symbol.clone(),
+            def,
+            matchers,
+        })
+    }
+}
+
+impl From<SymbolRule> for SymbolRuleDef {
+    fn from(rule: SymbolRule) -> Self {
+        rule.def
+    }
+}
+
+impl SymbolRule {
+    /// Returns true if all patterns of the rule match the window
+    pub fn is_match(&self, window: &WindowInfo) -> bool {
+        self.matchers
+            .iter()
+            .all(|(field, matcher)| field.is_match(window, matcher))
+    }
+}
+
+/// Names of windows without a symbol that were already logged
+static UNMATCHED: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// Logs windows without a matching symbol once per application, so mappings can be added for
+/// them. Applications are told apart by the name the fallback uses, since the initial title
+/// differs from window to window
+fn log_unmatched(window: &WindowInfo) {
+    let names = window.names();
+    let Some(name) = names.first() else {
+        return;
+    };
+    if UNMATCHED.lock().unwrap().insert(name.to_string()) {
+        info!("No symbol configured for window [{}]", names.join(", "));
+    }
 }
 
 impl SwayNameManagerConfig {
-    pub fn get_symbol(&self, names: &[&str]) -> String {
-        names
+    /// Returns the symbol for the window. Exact matches of its names in `app_symbols` are checked
+    /// first, then the `rules` in order. Without a match the `fallback` is used. `None` means the
+    /// window should be hidden
+    pub fn get_symbol(&self, window: &WindowInfo) -> Option<String> {
+        let names = window.names();
+        let symbol = names
             .iter()
             .find_map(|val| self.app_symbols.get(*val))
-            .map(|val| val.to_string())
-            .unwrap_or(names.first().unwrap_or(&"*invalid*").to_string())
-    }
-    pub fn from_file(config_path: &PathBuf) -> Self {
-        let file_result = File::open(config_path);
-        match file_result {
-            Ok(config_file) => {
-                let serde_result = serde_yaml::from_reader(config_file);
-                match serde_result {
-                    Ok(result) => {
-                        return result;
-                    }
-                    Err(e) => {
-                        error!("Error while reading config: {e}. Using default config")
-                    }
-                }
+            .or_else(|| {
+                self.rules
+                    .iter()
+                    .find(|rule| rule.is_match(window))
+                    .map(|rule| &rule.symbol)
+            });
+        match symbol {
+            Some(symbol) => Some(symbol.to_string()),
+            None => {
+                log_unmatched(window);
+                self.fallback.apply(names.first().copied())
             }
+        }
+    }
+
+    /// Whether names are Pango markup
+    pub fn uses_markup(&self) -> bool {
+        self.markup || self.fullscreen_color.is_some() || !self.styles.is_empty()
+    }
+
+    /// Returns the symbol for the window like [`Self::get_symbol`]. If names are markup, it is
+    /// escaped and styled
+    pub fn render_symbol(&self, window: &WindowInfo, state: WindowState) -> Option<String> {
+        let symbol = self.get_symbol(window)?;
+        if !self.uses_markup() {
+            return Some(symbol);
+        }
+        let fullscreen = self.fullscreen_color.as_ref().map(|color| Style {
+            foreground: Some(color.clone()),
+            ..Default::default()
+        });
+        let style = self.styles.get(window, state, fullscreen.as_ref());
+        Some(style.apply(&escape(&symbol)))
+    }
+
+    /// Loads the config from the file. A missing file falls back to the default config, but a
+    /// file which can't be parsed, e.g. because of an invalid pattern in `rules`, is an error
+    pub fn from_file(config_path: &PathBuf) -> Result<Self> {
+        match File::open(config_path) {
+            Ok(config_file) => serde_yaml::from_reader(config_file)
+                .map_err(|e| anyhow!("Error while reading config {config_path:?}: {e}")),
             Err(e) => {
                 error!("Failed to open config file: {e}. Using default config");
+                Ok(Self::default())
             }
         }
-        Self {
-            ..Default::default()
-        }
     }
 }
@@ -0,0 +1,382 @@
+//! UNIX socket for other tools. Every line sent to it is a request, which is answered with a
+//! JSON line:
+//! - `get_names`: the state of all workspaces
+//! - `get_config`: the config in use
+//! - `reload`: reloads the config and renames all workspaces
+//! - `refresh`: renames all workspaces
+//! - `pause`: stops renaming until `resume`
+//! - `resume`: renames all workspaces and keeps them updated again
+//! - `status`: whether renaming is paused, the config file and the number of workspaces
+//! - `quit`: restores the names if requested and exits
+//! - `subscribe`: the state of all workspaces, again whenever it changes
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Result, anyhow};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::{
+    io::Lines,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream, unix::OwnedWriteHalf},
+    sync::watch,
+};
+
+use crate::config::SwayNameManagerConfig;
+use crate::wm::{Output, WorkspaceState};
+
+/// Identifies the compositor session, so instances of different sessions on the same machine
+/// don't interfere. Derived from the file name of the IPC socket or the instance signature
+fn session() -> Option<String> {
+    let value = [
+        "SWAYSOCK",
+        "HYPRLAND_INSTANCE_SIGNATURE",
+        "I3SOCK",
+        "NIRI_SOCKET",
+    ]
+    .into_iter()
+    .find_map(env::var_os)?;
+    let name = Path::new(&value).file_stem()?.to_string_lossy();
+    Some(
+        name.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect(),
+    )
+}
+
+/// `$XDG_RUNTIME_DIR/swayautonames-<session>.sock`. The mock window manager uses the session
+/// `mock`, so it does not interfere with the instance of the real session
+pub fn socket_path(mock: bool) -> Result<PathBuf> {
+    let runtime_dir =
+        env::var_os("XDG_RUNTIME_DIR").ok_or(anyhow!("XDG_RUNTIME_DIR is not set"))?;
+    let session = if mock {
+        Some("mock".to_string())
+    } else {
+        session()
+    };
+    let name = match session {
+        Some(session) => format!("swayautonames-{session}.sock"),
+        None => "swayautonames.sock".to_string(),
+    };
+    Ok(PathBuf::from(runtime_dir).join(name))
+}
+
+/// A workspace as listed in replies
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Workspace {
+    pub id: i32,
+    #[serde(flatten)]
+    pub state: WorkspaceState,
+}
+
+/// The reply to `get_names` and every line sent after `subscribe`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Names {
+    pub workspaces: Vec<Workspace>,
+    /// The names are Pango markup
+    pub markup: bool,
+}
+
+/// The state requests are answered from
+#[derive(Clone)]
+pub struct Daemon {
+    pub config: Arc<RwLock<SwayNameManagerConfig>>,
+    /// The file the config is reloaded from. The default config is kept without it
+    pub config_path: Option<PathBuf>,
+    /// Notifies the managers about config reloads
+    pub resync: watch::Sender<()>,
+    /// Shared with the managers
+    pub output: Output,
+    /// Stops the managers
+    pub shutdown: watch::Sender<()>,
+}
+
+impl Daemon {
+    /// Reloads the config from its file and lets the managers rename all workspaces. The old
+    /// config is kept if the file is invalid
+    pub fn reload(&self) -> Result<()> {
+        if let Some(config_path) = &self.config_path {
+            let config = SwayNameManagerConfig::from_file(config_path)?;
+            *self.config.write().unwrap() = config;
+        }
+        self.resync.send_replace(());
+        Ok(())
+    }
+
+    fn names(&self) -> Value {
+        let workspaces = self
+            .output
+            .workspaces
+            .borrow()
+            .iter()
+            .map(|(id, state)| Workspace {
+                id: *id,
+                state: state.clone(),
+            })
+            .collect();
+        let names = Names {
+            workspaces,
+            markup: self.config.read().unwrap().uses_markup(),
+        };
+        json!(names)
+    }
+
+    /// Answers all requests except `subscribe`
+    fn handle(&self, request: &str) -> Value {
+        match request {
+            "get_names" => self.names(),
+            "get_config" => json!({ "config": *self.config.read().unwrap() }),
+            "reload" => {
+                info!("Reloading the config on request");
+                match self.reload() {
+                    Ok(()) => json!({ "success": true }),
+                    Err(err) => {
+                        error!("Keeping the old config: {err}");
+                        json!({ "error": err.to_string() })
+                    }
+                }
+            }
+            "refresh" => {
+                self.resync.send_replace(());
+                json!({ "success": true })
+            }
+            "pause" => {
+                info!("Pausing on request");
+                self.output.paused.send_replace(true);
+                json!({ "success": true })
+            }
+            "resume" => {
+                info!("Resuming on request");
+                // Catch up on the changes while paused
+                if self.output.paused.send_replace(false) {
+                    self.resync.send_replace(());
+                }
+                json!({ "success": true })
+            }
+            "quit" => {
+                info!("Quitting on request");
+                self.shutdown.send_replace(());
+                json!({ "success": true })
+            }
+            "status" => json!({
+                "paused": *self.output.paused.borrow(),
+                "config_path": self.config_path,
+                "workspaces": self.output.workspaces.borrow().len(),
+            }),
+            _ => json!({ "error": format!("Unknown request {request}") }),
+        }
+    }
+
+    /// Sends the names now and whenever they change, until the client disconnects
+    async fn subscribe(&self, writer: &mut OwnedWriteHalf) -> Result<()> {
+        let mut workspaces = self.output.workspaces.subscribe();
+        loop {
+            send(writer, &self.names()).await?;
+            if workspaces.changed().await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn handle_client(&self, stream: UnixStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            match line.trim() {
+                "subscribe" => return self.subscribe(&mut writer).await,
+                request => send(&mut writer, &self.handle(request)).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers requests on the socket at `path` until accepting fails
+    pub async fn serve(&self, path: &Path) -> Result<()> {
+        // A socket left behind by a crashed instance
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        info!("Listening on {path:?}");
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let daemon = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = daemon.handle_client(stream).await {
+                    error!("IPC client failed: {err}");
+                }
+            });
+        }
+    }
+}
+
+/// Sends a request to the running instance, or the one running the mock, and returns its reply
+pub async fn request(mock: bool, request: &str) -> Result<Value> {
+    request_at(&socket_path(mock)?, request).await
+}
+
+/// Sends a request to the instance listening on `path` and returns its reply
+pub async fn request_at(path: &Path, request: &str) -> Result<Value> {
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|err| anyhow!("No instance is listening on {path:?}: {err}"))?;
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(format!("{request}\n").as_bytes()).await?;
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or(anyhow!("The instance closed the connection"))?;
+    let reply: Value = serde_json::from_str(&line)?;
+    match reply["error"].as_str() {
+        Some(err) => Err(anyhow!("{err}")),
+        None => Ok(reply),
+    }
+}
+
+/// Subscribes to the names of the instance listening on `path`. Every line is a [`Names`], until
+/// the instance closes the connection
+pub async fn subscribe(path: &Path) -> Result<Lines<BufReader<UnixStream>>> {
+    let mut stream = UnixStream::connect(path)
+        .await
+        .map_err(|err| anyhow!("No instance is listening on {path:?}: {err}"))?;
+    stream.write_all(b"subscribe\n").await?;
+    Ok(BufReader::new(stream).lines())
+}
+
+async fn send(writer: &mut OwnedWriteHalf, reply: &Value) -> Result<()> {
+    writer.write_all(format!("{reply}\n").as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn state(name: &str) -> WorkspaceState {
+        WorkspaceState {
+            num: 1,
+            name: name.to_string(),
+            focused: false,
+            urgent: false,
+            fullscreen: false,
+            windows: vec![],
+        }
+    }
+
+    fn daemon() -> Daemon {
+        let output = Output::default();
+        output
+            .workspaces
+            .send_replace(BTreeMap::from([(10, state("1: F"))]));
+        Daemon {
+            config: Default::default(),
+            config_path: None,
+            resync: watch::Sender::new(()),
+            output,
+            shutdown: watch::Sender::new(()),
+        }
+    }
+
+    #[test]
+    fn answers_requests() {
+        let daemon = daemon();
+        assert_eq!(
+            daemon.handle("get_names"),
+            json!({ "workspaces": [{
+                "id": 10,
+                "num": 1,
+                "name": "1: F",
+                "focused": false,
+                "urgent": false,
+                "fullscreen": false,
+                "windows": [],
+            }],
+            "markup": false,
+            })
+        );
+        assert_eq!(daemon.handle("get_config")["config"]["separator"], "|");
+        assert!(daemon.handle("foo")["error"].is_string());
+
+        let resync = daemon.resync.subscribe();
+        assert_eq!(daemon.handle("reload"), json!({ "success": true }));
+        assert!(resync.has_changed().unwrap());
+    }
+
+    #[test]
+    fn reload_keeps_config_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        let daemon = Daemon {
+            config_path: Some(config_path.clone()),
+            ..daemon()
+        };
+        std::fs::write(&config_path, r#"{ "separator": " " }"#).unwrap();
+        assert_eq!(daemon.handle("reload"), json!({ "success": true }));
+        assert_eq!(daemon.handle("get_config")["config"]["separator"], " ");
+
+        let resync = daemon.resync.subscribe();
+        std::fs::write(
+            &config_path,
+            r#"{ "separator": "-", "rules": [{ "match": "(", "symbol": "x" }] }"#,
+        )
+        .unwrap();
+        assert!(daemon.handle("reload")["error"].is_string());
+        assert_eq!(daemon.handle("get_config")["config"]["separator"], " ");
+        assert!(!resync.has_changed().unwrap());
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let daemon = daemon();
+        let mut resync = daemon.resync.subscribe();
+        assert_eq!(daemon.handle("status")["paused"], false);
+        daemon.handle("pause");
+        assert!(!daemon.output.renames());
+        assert_eq!(daemon.handle("status")["paused"], true);
+
+        daemon.handle("resume");
+        assert!(daemon.output.renames());
+        assert!(resync.has_changed().unwrap());
+        resync.mark_unchanged();
+        // Resuming without pausing does not rename anything
+        daemon.handle("resume");
+        assert!(!resync.has_changed().unwrap());
+    }
+
+    #[tokio::test]
+    async fn subscribe_streams_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("swayautonames.sock");
+        let daemon = daemon();
+        let workspaces = daemon.output.workspaces.clone();
+        let server_path = path.clone();
+        tokio::spawn(async move { daemon.serve(&server_path).await });
+        while !path.exists() {
+            tokio::task::yield_now().await;
+        }
+
+        let (reader, mut writer) = UnixStream::connect(&path).await.unwrap().into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"subscribe\n").await.unwrap();
+        let names = |line: Option<String>| -> Value {
+            serde_json::from_str::<Value>(&line.unwrap()).unwrap()["workspaces"][0]["name"].clone()
+        };
+        assert_eq!(names(lines.next_line().await.unwrap()), "1: F");
+        workspaces.send_modify(|workspaces| workspaces.get_mut(&10).unwrap().name = "1".into());
+        assert_eq!(names(lines.next_line().await.unwrap()), "1");
+    }
+}
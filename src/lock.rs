@@ -0,0 +1,104 @@
+//! Makes sure only one instance renames the workspaces of a session. The lock is a file next to
+//! the IPC socket, which is locked as long as the instance runs
+
+use std::{
+    fs::{File, TryLockError},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use log::info;
+
+use crate::ipc;
+
+/// How often the lock is tried after asking the running instance to quit. It might restore the
+/// names first
+const REPLACE_ATTEMPTS: u32 = 100;
+const REPLACE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Held until the instance exits
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// Returns false if another instance holds the lock
+fn try_lock(file: &File) -> Result<bool> {
+    match file.try_lock() {
+        Ok(()) => Ok(true),
+        Err(TryLockError::WouldBlock) => Ok(false),
+        Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+}
+
+impl InstanceLock {
+    /// Locks the file at `path`. If another instance holds it, this fails unless `replace` is
+    /// set. Then the instance listening on `socket` is asked to quit and its lock is taken over
+    pub async fn acquire(path: &Path, socket: &Path, replace: bool) -> Result<Self> {
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        if try_lock(&file)? {
+            return Ok(Self { _file: file });
+        }
+        if !replace {
+            return Err(anyhow!(
+                "swayautonames is already running in this session. Use --replace to take over"
+            ));
+        }
+        info!("Asking the running instance to quit");
+        ipc::request_at(socket, "quit").await?;
+        for _ in 0..REPLACE_ATTEMPTS {
+            tokio::time::sleep(REPLACE_INTERVAL).await;
+            if try_lock(&file)? {
+                return Ok(Self { _file: file });
+            }
+        }
+        Err(anyhow!("The running instance did not quit"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::watch;
+
+    use super::*;
+    use crate::ipc::Daemon;
+
+    #[tokio::test]
+    async fn rejects_second_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, socket) = (dir.path().join("lock"), dir.path().join("sock"));
+        let _lock = InstanceLock::acquire(&path, &socket, false).await.unwrap();
+        assert!(InstanceLock::acquire(&path, &socket, false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn replaces_running_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, socket) = (dir.path().join("lock"), dir.path().join("sock"));
+        let lock = InstanceLock::acquire(&path, &socket, false).await.unwrap();
+        let daemon = Daemon {
+            config: Default::default(),
+            config_path: None,
+            resync: watch::Sender::new(()),
+            output: Default::default(),
+            shutdown: watch::Sender::new(()),
+        };
+        // The running instance releases its lock on shutdown
+        let mut shutdown = daemon.shutdown.subscribe();
+        tokio::spawn(async move {
+            shutdown.changed().await.unwrap();
+            drop(lock);
+        });
+        let server_socket = socket.clone();
+        tokio::spawn(async move { daemon.serve(&server_socket).await });
+        while !socket.exists() {
+            tokio::task::yield_now().await;
+        }
+        InstanceLock::acquire(&path, &socket, true).await.unwrap();
+    }
+}
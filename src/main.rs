@@ -3,36 +3,130 @@ use std::{
     sync::{Arc, RwLock},
 };
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use futures_util::stream::StreamExt;
 use inotify::{EventMask, Inotify, WatchMask};
 use log::*;
 use simplelog::{ColorChoice, Config, LevelFilter, TermLogger, TerminalMode};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::watch,
+    task::JoinSet,
+};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, error::ErrorKind};
 
 use crate::config::SwayNameManagerConfig;
+use crate::rename::Rename;
 
 mod config;
+mod ipc;
+mod lock;
+mod markup;
+mod naming;
+mod rename;
+mod waybar;
 mod wm;
 
+/// Manager for sway and i3, which share the same IPC protocol
+#[cfg(any(feature = "sway", feature = "i3"))]
 struct SwayNameManager {
     config: Arc<RwLock<SwayNameManagerConfig>>,
+    /// Path of the IPC socket. `None` if the environment variable for it is not set
+    socket_path: Option<PathBuf>,
+    /// Connection for commands. Events are received on a separate connection
+    connection: tokio::sync::Mutex<Option<swayipc_async::Connection>>,
+    initial_titles: std::sync::Mutex<wm::sway::InitialTitles>,
+    /// Changes when the config was reloaded
+    resync: watch::Receiver<()>,
+    /// Workspaces are identified by their number, windows by their container id
+    tracker: wm::Tracker<i64>,
 }
 
 trait WindowManager {
-    fn get_workspaces(&self) -> Result<Vec<i32>>;
-    fn get_workspace_name(&self, id: i32) -> Result<String>;
-    fn update_workspace(&self, id: i32, name: &str) -> Result<()>;
-
-    fn update_all(&self) -> Result<()> {
-        let workspaces = self.get_workspaces()?;
-        for i in workspaces {
-            let name = self.get_workspace_name(i)?;
-            self.update_workspace(i, &format!("{i}:{name}"))?;
+    /// Returns the id and the current name of every workspace
+    async fn get_workspaces(&self) -> Result<Vec<(i32, String)>>;
+    /// Returns the name the workspace should have according to the config
+    async fn get_workspace_name(&self, id: i32) -> Result<String>;
+    /// Returns the name of the workspace without symbols or label, i.e. its number
+    async fn get_bare_name(&self, id: i32) -> Result<String> {
+        Ok(id.to_string())
+    }
+    /// Returns the name the workspace had before it was renamed the first time
+    fn get_original_name(&self, id: i32) -> Option<String>;
+    async fn update_workspace(&self, id: i32, name: &str) -> Result<()>;
+
+    /// Renames multiple workspaces at once. Backends supporting batched commands override this
+    async fn update_workspaces(&self, names: &[(i32, String)]) -> Result<()> {
+        for (id, name) in names {
+            self.update_workspace(*id, name).await?;
         }
         Ok(())
     }
+
+    /// Backends that can fetch all workspaces and windows at once override this, so they aren't
+    /// fetched once per workspace
+    async fn update_all(&self) -> Result<()> {
+        let mut names = vec![];
+        for (id, _) in self.get_workspaces().await? {
+            names.push((id, self.get_workspace_name(id).await?));
+        }
+        self.update_workspaces(&names).await
+    }
+
+    /// Returns the renames `update_all` would do in order, without renaming anything
+    async fn plan_all(&self) -> Result<Vec<Rename<i32>>> {
+        let workspaces = self.get_workspaces().await?;
+        let mut renames = vec![];
+        for (id, name) in &workspaces {
+            renames.push(Rename {
+                id: *id,
+                old: name.clone(),
+                new: self.get_workspace_name(*id).await?,
+            });
+        }
+        let names: Vec<String> = workspaces.into_iter().map(|(_, name)| name).collect();
+        Ok(rename::plan(renames, &names))
+    }
+
+    /// Renames all workspaces back, e.g. before exiting
+    async fn restore(&self, restore: Restore) -> Result<()> {
+        let mut names = vec![];
+        for (id, _) in self.get_workspaces().await? {
+            let original = match restore {
+                Restore::Number => None,
+                Restore::Original => self.get_original_name(id),
+            };
+            let name = match original {
+                Some(name) => name,
+                None => self.get_bare_name(id).await?,
+            };
+            names.push((id, name));
+        }
+        self.update_workspaces(&names).await
+    }
+}
+
+/// The names workspaces are renamed to when exiting
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restore {
+    /// The number of the workspace
+    Number,
+    /// The name the workspace had before it was renamed the first time. Falls back to the number
+    Original,
+}
+
+/// What is done with the names of the workspaces
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Rename the workspaces
+    #[default]
+    Rename,
+    /// Print the JSON lines of a workspace for a Waybar custom module, as named by the running
+    /// instance. Doesn't need `--window-manager`
+    Waybar,
+    /// Rename the workspaces and print the JSON lines of a workspace
+    Both,
 }
 
 #[derive(clap::ValueEnum, Debug, Clone, PartialEq, Eq)]
@@ -41,6 +135,12 @@ pub enum WindowManagerType {
     Hyprland,
     #[cfg(feature = "sway")]
     Sway,
+    #[cfg(feature = "i3")]
+    I3,
+    #[cfg(feature = "niri")]
+    Niri,
+    /// In-memory window manager loaded from `--scenario`. Not part of `all`
+    Mock,
     All,
 }
 
@@ -51,8 +151,123 @@ struct Args {
     #[arg(short, long)]
     config: Option<PathBuf>,
 
+    /// Required unless talking to a running instance. `mock` talks to the instance running the
+    /// mock instead
     #[arg(short, long)]
-    window_manager: WindowManagerType,
+    window_manager: Option<WindowManagerType>,
+
+    /// JSON file with the workspaces and windows of the mock window manager
+    #[arg(long)]
+    scenario: Option<PathBuf>,
+
+    /// Rename the workspaces back on SIGINT and SIGTERM
+    #[arg(long)]
+    restore: Option<Restore>,
+
+    /// Ask the instance already running in this session to quit and take over
+    #[arg(long)]
+    replace: bool,
+
+    /// Rename the workspaces, print them for Waybar or both
+    #[arg(long, value_enum, default_value_t)]
+    output: OutputMode,
+
+    /// The workspace whose Waybar line is printed. Every Waybar module shows one workspace
+    #[arg(long, required_if_eq_any([("output", "waybar"), ("output", "both")]))]
+    waybar_workspace: Option<i32>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug, Clone, PartialEq, Eq)]
+enum Command {
+    /// Rename all workspaces back to their number and exit
+    Reset,
+    /// Rename all workspaces once and exit
+    Once {
+        /// Only print the planned renames
+        #[arg(long)]
+        dry_run: bool,
+    },
+    #[command(flatten)]
+    Client(ClientCommand),
+}
+
+/// Commands sent to the running instance
+#[derive(clap::Subcommand, Debug, Clone, PartialEq, Eq)]
+enum ClientCommand {
+    /// Reload the config and rename all workspaces
+    Reload,
+    /// Print whether renaming is paused, the config file and the number of workspaces
+    Status,
+    /// Stop renaming workspaces, e.g. while sharing the screen
+    Pause,
+    /// Rename all workspaces and keep them updated again
+    Resume,
+    /// Rename all workspaces
+    Refresh,
+}
+
+impl ClientCommand {
+    /// The IPC request of the command
+    fn request(&self) -> &'static str {
+        match self {
+            ClientCommand::Reload => "reload",
+            ClientCommand::Status => "status",
+            ClientCommand::Pause => "pause",
+            ClientCommand::Resume => "resume",
+            ClientCommand::Refresh => "refresh",
+        }
+    }
+}
+
+/// How the managers are run
+#[derive(Clone)]
+struct RunOptions {
+    command: Option<Command>,
+    /// Changes on SIGINT and SIGTERM
+    shutdown: watch::Receiver<()>,
+    restore: Option<Restore>,
+}
+
+/// Runs the manager with `run` until shutdown and restores the names afterwards if requested.
+/// The one-shot commands are run instead if given
+async fn run_manager<M: WindowManager>(
+    manager: M,
+    mut options: RunOptions,
+    run: impl AsyncFnOnce(&M) -> Result<()>,
+) -> Result<()> {
+    match options.command {
+        Some(Command::Reset) => return manager.restore(Restore::Number).await,
+        Some(Command::Once { dry_run: false }) => return manager.update_all().await,
+        Some(Command::Once { dry_run: true }) => {
+            for rename in manager.plan_all().await? {
+                println!("{} → {}", rename.old, rename.new);
+            }
+            return Ok(());
+        }
+        Some(Command::Client(_)) | None => {}
+    }
+    tokio::select! {
+        result = run(&manager) => return result,
+        _ = options.shutdown.changed() => {}
+    }
+    match options.restore {
+        Some(restore) => manager.restore(restore).await,
+        None => Ok(()),
+    }
+}
+
+/// Waits for SIGINT or SIGTERM
+async fn wait_for_signal() -> Result<()> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = interrupt.recv() => {}
+        _ = terminate.recv() => {}
+    }
+    Ok(())
 }
 
 fn get_config_paths(aditional_paths: &Option<PathBuf>) -> Vec<PathBuf> {
@@ -89,69 +304,215 @@ fn get_config_path(aditional_paths: Option<PathBuf>) -> Option<PathBuf> {
     selected_config
 }
 
+/// Reloads the config whenever the file changes
+async fn watch_config(config_path: &PathBuf, daemon: &ipc::Daemon) -> Result<()> {
+    let inotify = Inotify::init()?;
+    let mask = WatchMask::MODIFY | WatchMask::CREATE | WatchMask::DELETE_SELF;
+    inotify.watches().add(config_path, mask)?;
+
+    let mut buffer = [0; 1024];
+    let mut stream = inotify.into_event_stream(&mut buffer)?;
+
+    while let Some(event_or_error) = stream.next().await {
+        if let Ok(event) = event_or_error
+            && event.mask.contains(EventMask::DELETE_SELF)
+        {
+            // Recreate inotify. Some editors delete the file and recreate it (e.g. neovim)
+            stream.watches().add(config_path, mask)?;
+        }
+        if let Err(err) = daemon.reload() {
+            error!("Keeping the old config: {err}");
+        }
+    }
+    Ok(())
+}
+
+/// Waits until all managers returned. Fails if none of them finished successfully, e.g. because
+/// all window managers are gone
+async fn wait_for_managers(mut managers: JoinSet<(&'static str, Result<()>)>) -> Result<()> {
+    let mut succeeded = false;
+    while let Some(joined) = managers.join_next().await {
+        match joined? {
+            (_, Ok(())) => succeeded = true,
+            (name, Err(err)) => error!("{name} failed permanently: {err}"),
+        }
+    }
+    if succeeded {
+        Ok(())
+    } else {
+        Err(anyhow!("No window manager is left"))
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = Args::parse();
+    let waybar = args.output != OutputMode::Rename;
+    // Stdout belongs to Waybar and to the output of one-shot commands, e.g. the planned renames
+    let terminal_mode = if waybar || args.command.is_some() {
+        TerminalMode::Stderr
+    } else {
+        TerminalMode::Stdout
+    };
     TermLogger::init(
         LevelFilter::Trace,
         Config::default(),
-        TerminalMode::Stdout,
+        terminal_mode,
         ColorChoice::Auto,
     )
     .unwrap();
-    let args = Args::parse();
+    // Talk to the instance running the mock instead of the one of the session
+    let mock = args.window_manager == Some(WindowManagerType::Mock);
+    if let Some(Command::Client(command)) = &args.command {
+        println!("{}", ipc::request(mock, command.request()).await?);
+        return Ok(());
+    }
+    // Waybar modules show the names of the running instance
+    if let Some(workspace) = args.waybar_workspace
+        && args.output == OutputMode::Waybar
+    {
+        let socket_path = ipc::socket_path(mock)?;
+        return waybar::subscribe(&socket_path, workspace, &mut tokio::io::stdout()).await;
+    }
+    let Some(window_manager) = args.window_manager.clone() else {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--window-manager is required unless talking to a running instance",
+            )
+            .exit();
+    };
+    if window_manager == WindowManagerType::Mock && args.scenario.is_none() {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--scenario is required to run the mock window manager",
+            )
+            .exit();
+    }
+    // One-shot commands don't interfere with the running instance. The lock is held until exiting
+    let lock = if args.command.is_none() {
+        match ipc::socket_path(mock) {
+            Ok(socket_path) => {
+                let lock_path = socket_path.with_extension("lock");
+                let lock =
+                    lock::InstanceLock::acquire(&lock_path, &socket_path, args.replace).await?;
+                Some((lock, socket_path))
+            }
+            Err(err) => {
+                error!("Not checking for other instances: {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
     let selected_config_path = get_config_path(args.config);
     info!("Starting swayautonames with config: {selected_config_path:?}");
     let config = Arc::new(RwLock::new(SwayNameManagerConfig::from_file(
         &selected_config_path.clone().unwrap_or_default(),
-    )));
-    #[cfg(feature = "sway")]
-    if args.window_manager == WindowManagerType::Sway
-        || args.window_manager == WindowManagerType::All
+    )?));
+    // Notifies the managers about config reloads
+    let (resync, _) = watch::channel(());
+    let (shutdown, _) = watch::channel(());
+    let options = RunOptions {
+        command: args.command.clone(),
+        shutdown: shutdown.subscribe(),
+        restore: args.restore,
+    };
+    // Shared by all managers, since only one window manager runs at a time
+    let output = wm::Output::default();
+    if let Some(workspace) = args.waybar_workspace
+        && args.output == OutputMode::Both
     {
-        let mut manager = SwayNameManager::new(config.clone());
+        let workspaces = output.workspaces.subscribe();
+        let config = config.clone();
         tokio::spawn(async move {
-            manager.run().await.unwrap();
+            if let Err(err) = waybar::run(workspaces, workspace, config).await {
+                error!("Waybar output failed: {err}");
+            }
         });
     }
+    let mut managers = JoinSet::new();
+    #[cfg(feature = "sway")]
+    if window_manager == WindowManagerType::Sway || window_manager == WindowManagerType::All {
+        let manager = SwayNameManager::new(config.clone(), resync.subscribe(), output.clone());
+        let result = run_manager(manager, options.clone(), async |m| m.run_forever().await);
+        managers.spawn(async move { ("SwayNameManager", result.await) });
+    }
+    #[cfg(feature = "i3")]
+    if window_manager == WindowManagerType::I3
+        || (window_manager == WindowManagerType::All && !wm::i3::is_sway())
+    {
+        let manager = SwayNameManager::new_i3(config.clone(), resync.subscribe(), output.clone());
+        let result = run_manager(manager, options.clone(), async |m| m.run_forever().await);
+        managers.spawn(async move { ("SwayNameManager (i3)", result.await) });
+    }
     #[cfg(feature = "hyprland")]
-    if args.window_manager == WindowManagerType::Hyprland
-        || args.window_manager == WindowManagerType::All
+    if window_manager == WindowManagerType::Hyprland || window_manager == WindowManagerType::All {
+        match wm::hyprland::HyprlandManager::new(config.clone(), resync.subscribe(), output.clone())
+        {
+            Ok(manager) => {
+                let result = run_manager(manager, options.clone(), async |m| m.run_forever().await);
+                managers.spawn(async move { ("HyprlandManager", result.await) });
+            }
+            Err(err) => error!("Could not connect to Hyprland: {err}"),
+        }
+    }
+    #[cfg(feature = "niri")]
+    if window_manager == WindowManagerType::Niri
+        || (window_manager == WindowManagerType::All && wm::niri::socket_path().is_ok())
+    {
+        let manager =
+            wm::niri::NiriManager::new(config.clone(), resync.subscribe(), output.clone());
+        let result = run_manager(manager, options.clone(), async |m| m.run_forever().await);
+        managers.spawn(async move { ("NiriManager", result.await) });
+    }
+    if window_manager == WindowManagerType::Mock
+        && let Some(scenario_path) = &args.scenario
     {
-        let hyprland_config = config.clone();
+        let scenario = wm::mock::Scenario::from_file(scenario_path)?;
+        let manager = wm::mock::MockManager::new(
+            config.clone(),
+            scenario,
+            resync.subscribe(),
+            output.clone(),
+        );
+        let result = run_manager(manager, options.clone(), async |m| m.run().await);
+        managers.spawn(async move { ("MockManager", result.await) });
+    }
+    let daemon = ipc::Daemon {
+        config: config.clone(),
+        config_path: selected_config_path.clone(),
+        resync,
+        output,
+        shutdown: shutdown.clone(),
+    };
+    tokio::spawn(async move {
+        match wait_for_signal().await {
+            Ok(()) => {
+                info!("Shutting down");
+                shutdown.send_replace(());
+            }
+            Err(err) => error!("Could not listen for signals: {err}"),
+        }
+    });
+    // Only the instance holding the lock listens
+    if let Some((_, socket_path)) = &lock {
+        let socket_path = socket_path.clone();
+        let daemon = daemon.clone();
         tokio::spawn(async move {
-            loop {
-                let res = wm::hyprland::HyprlandManager {
-                    config: hyprland_config.clone(),
-                }
-                .run()
-                .await;
-                match res {
-                    Ok(_) => break,
-                    Err(err) => {
-                        error!("Recreating because HyprlandManager returned with error: {err}");
-                    }
-                }
+            if let Err(err) = daemon.serve(&socket_path).await {
+                error!("IPC socket failed: {err}");
             }
         });
     }
-    if let Some(config_path) = &selected_config_path {
-        let inotify = Inotify::init()?;
-        let mask = WatchMask::MODIFY | WatchMask::CREATE | WatchMask::DELETE_SELF;
-        inotify.watches().add(config_path, mask)?;
-
-        let mut buffer = [0; 1024];
-        let mut stream = inotify.into_event_stream(&mut buffer)?;
-
-        while let Some(event_or_error) = stream.next().await {
-            if let Ok(event) = event_or_error
-                && event.mask.contains(EventMask::DELETE_SELF)
-            {
-                // Recreate inotify. Some editors delete the file and recreate it (e.g. neovim)
-                stream.watches().add(config_path, mask)?;
-            }
-            let new_config = SwayNameManagerConfig::from_file(config_path);
-            *config.write().unwrap() = new_config.clone();
-        }
+    let managers = wait_for_managers(managers);
+    match &selected_config_path {
+        Some(config_path) => tokio::select! {
+            result = managers => result,
+            result = watch_config(config_path, &daemon) => result,
+        },
+        None => managers.await,
     }
-    Ok(())
 }
@@ -0,0 +1,172 @@
+//! Pango markup of workspace names. Waybar, swaybar with `pango_markup enabled` and Hyprland
+//! bars render it
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::wm::WindowInfo;
+
+/// Escapes text, so it is shown as is
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverts [`escape`]. Text that was not escaped is returned as is
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Attributes of a `<span>`. Unset attributes are inherited from less specific styles
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Style {
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    /// A Pango font description like `Sans Bold 10`
+    pub font: Option<String>,
+}
+
+impl Style {
+    /// Overrides the attributes set in `other`
+    fn merge(&mut self, other: &Style) {
+        for (attribute, value) in [
+            (&mut self.foreground, &other.foreground),
+            (&mut self.background, &other.background),
+            (&mut self.font, &other.font),
+        ] {
+            if value.is_some() {
+                attribute.clone_from(value);
+            }
+        }
+    }
+
+    /// Wraps the markup in a span with the attributes. Without attributes it is returned as is
+    pub fn apply(&self, markup: &str) -> String {
+        let attributes: String = [
+            ("foreground", &self.foreground),
+            ("background", &self.background),
+            ("font", &self.font),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some(format!(r#" {name}="{}""#, escape(value.as_ref()?))))
+        .collect();
+        if attributes.is_empty() {
+            markup.to_string()
+        } else {
+            format!("<span{attributes}>{markup}</span>")
+        }
+    }
+}
+
+/// The state of a window its style depends on
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WindowState {
+    pub focused: bool,
+    pub urgent: bool,
+    pub floating: bool,
+    pub fullscreen: bool,
+}
+
+/// Styles of the symbols. The style of the application is applied first, followed by the ones
+/// of the window state in the order `floating`, `fullscreen`, `urgent` and `focused`
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Styles {
+    /// Style by application name, matched like `app_symbols`
+    pub app: HashMap<String, Style>,
+    pub floating: Option<Style>,
+    pub fullscreen: Option<Style>,
+    pub urgent: Option<Style>,
+    pub focused: Option<Style>,
+}
+
+impl Styles {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The combined style of a window. `fullscreen` is used for fullscreen windows if no
+    /// fullscreen style is configured
+    pub fn get(
+        &self,
+        window: &WindowInfo,
+        state: WindowState,
+        fullscreen: Option<&Style>,
+    ) -> Style {
+        let mut style = window
+            .names()
+            .iter()
+            .find_map(|name| self.app.get(*name))
+            .cloned()
+            .unwrap_or_default();
+        for (set, state_style) in [
+            (state.floating, self.floating.as_ref()),
+            (state.fullscreen, self.fullscreen.as_ref().or(fullscreen)),
+            (state.urgent, self.urgent.as_ref()),
+            (state.focused, self.focused.as_ref()),
+        ] {
+            if let (true, Some(state_style)) = (set, state_style) {
+                style.merge(state_style);
+            }
+        }
+        style
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_roundtrip() {
+        let text = r#"R&D <"tom's">"#;
+        assert_eq!(escape(text), "R&amp;D &lt;&quot;tom&apos;s&quot;&gt;");
+        assert_eq!(unescape(&escape(text)), text);
+        assert_eq!(unescape("R&D"), "R&D");
+    }
+
+    #[test]
+    fn states_override_app() {
+        let styles: Styles = serde_json::from_value(serde_json::json!({
+            "app": { "firefox": { "foreground": "orange", "font": "Sans 10" } },
+            "urgent": { "foreground": "red" },
+            "focused": { "background": "#333" },
+        }))
+        .unwrap();
+        let firefox = WindowInfo {
+            app_id: Some("firefox".to_string()),
+            ..Default::default()
+        };
+        let state = WindowState {
+            focused: true,
+            urgent: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            styles.get(&firefox, state, None).apply("F"),
+            r##"<span foreground="red" background="#333" font="Sans 10">F</span>"##
+        );
+        assert_eq!(
+            styles
+                .get(&WindowInfo::default(), WindowState::default(), None)
+                .apply("F"),
+            "F"
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use regex::Regex;
+
+use crate::config::{DedupConfig, SwayNameManagerConfig};
+use crate::markup;
+
+/// Replaces every `{key}` in `template` with its value. Unknown placeholders are kept as they are
+pub fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    render(template, values, str::to_string)
+}
+
+/// Builds a regex matching what `template` renders to. The text around the placeholders is
+/// escaped and the values are inserted as patterns
+fn template_pattern(template: &str, values: &[(&str, &str)]) -> String {
+    render(template, values, regex::escape)
+}
+
+/// Replaces every `{key}` in `template` with its value. The remaining text is passed through
+/// `literal`
+fn render(template: &str, values: &[(&str, &str)], literal: fn(&str) -> String) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&literal(&rest[..start]));
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let key = &rest[1..end];
+            values
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| (*value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                result.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push_str(&literal("{"));
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(&literal(rest));
+    result
+}
+
+fn superscript(num: usize) -> String {
+    num.to_string()
+        .chars()
+        .map(|digit| match digit {
+            '0' => '⁰',
+            '1' => '¹',
+            '2' => '²',
+            '3' => '³',
+            '4' => '⁴',
+            '5' => '⁵',
+            '6' => '⁶',
+            '7' => '⁷',
+            '8' => '⁸',
+            '9' => '⁹',
+            other => other,
+        })
+        .collect()
+}
+
+/// Replaces symbols occurring at least `threshold` times with a single counted symbol at the
+/// position of their first occurrence
+fn dedup_symbols(dedup: &DedupConfig, symbols: &[String]) -> Vec<String> {
+    let mut result = vec![];
+    for (i, symbol) in symbols.iter().enumerate() {
+        let count = symbols.iter().filter(|other| *other == symbol).count();
+        if count < dedup.threshold.max(1) {
+            result.push(symbol.clone());
+        } else if !symbols[..i].contains(symbol) {
+            let count_str = count.to_string();
+            result.push(render_template(
+                &dedup.format,
+                &[
+                    ("symbol", symbol),
+                    ("count", &count_str),
+                    ("count_superscript", &superscript(count)),
+                ],
+            ));
+        }
+    }
+    result
+}
+
+/// Returns the label of a name rendered from the `format` or `empty_format` of the config.
+/// `None` means the name was not rendered from them, e.g. because the user just renamed the
+/// workspace. Names are read without a label first, since the lazy label could otherwise swallow
+/// icons containing the text following it
+fn parse_rendered_label(config: &SwayNameManagerConfig, name: &str, num: &str) -> Option<String> {
+    let label = format!(
+        "(?:{})?",
+        template_pattern(&config.label_format, &[("label", "(?P<label>.*?)")])
+    );
+    let num = regex::escape(num);
+    ["", label.as_str()]
+        .into_iter()
+        .flat_map(|label| [(&config.format, label), (&config.empty_format, label)])
+        .find_map(|(template, label)| {
+            let pattern = template_pattern(
+                template,
+                &[("num", &num), ("label", label), ("icons", ".*")],
+            );
+            Regex::new(&format!("^{pattern}$")).ok()?.captures(name)
+        })
+        .map(|captures| {
+            captures
+                .name("label")
+                .map(|label| label.as_str().trim().to_string())
+                .unwrap_or_default()
+        })
+}
+
+/// Returns the label the user gave a workspace by renaming it to `num:label` or
+/// `num:label: icons`. Names in the form `num: icons` have no label
+fn parse_typed_label<'a>(name: &'a str, num: &str) -> Option<&'a str> {
+    let rest = name.strip_prefix(num)?.strip_prefix(':')?;
+    if rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let label = rest.split(':').next()?.trim();
+    (!label.is_empty()).then_some(label)
+}
+
+/// Whether the name was rendered from the config or is in the `num:label` form. Other names were
+/// given by someone else, e.g. in the niri config
+#[cfg(feature = "niri")]
+pub fn is_managed(config: &SwayNameManagerConfig, name: &str, num: &str) -> bool {
+    parse_rendered_label(config, name, num).is_some() || parse_typed_label(name, num).is_some()
+}
+
+/// Returns the label of the current name of a workspace. Names rendered from the config are
+/// parsed by inverting its templates, any other name as typed by the user
+pub fn parse_label(config: &SwayNameManagerConfig, name: &str, num: &str) -> Option<String> {
+    let label = match parse_rendered_label(config, name, num) {
+        Some(label) => label,
+        None => parse_typed_label(name, num)?.to_string(),
+    };
+    (!label.is_empty()).then_some(label)
+}
+
+/// Removes what was rendered from the config from a name, e.g. by a previous run, so only the
+/// number and the label typed by the user are left. Other names are returned as they are
+pub fn bare_name(config: &SwayNameManagerConfig, name: &str, num: &str) -> String {
+    match parse_rendered_label(config, name, num) {
+        Some(label) if label.is_empty() => num.to_string(),
+        Some(label) if config.uses_markup() => format!("{num}:{}", markup::unescape(&label)),
+        Some(label) => format!("{num}:{label}"),
+        None => name.to_string(),
+    }
+}
+
+/// Builds the name of workspace `num` from the symbols of its windows according to the format
+/// of the config. The label is taken from the current name of the workspace if the format
+/// contains a `{label}`. The symbols have to be escaped already if names are markup
+pub fn workspace_name(
+    config: &SwayNameManagerConfig,
+    num: &str,
+    current_name: &str,
+    symbols: &[String],
+) -> String {
+    let label = parse_label(config, current_name, num)
+        .map(|label| {
+            // The label of a markup name is escaped already, unless the user just renamed it
+            let label = if config.uses_markup() {
+                markup::escape(&markup::unescape(&label))
+            } else {
+                label
+            };
+            render_template(&config.label_format, &[("label", &label)])
+        })
+        .unwrap_or_default();
+    if symbols.is_empty() {
+        return render_template(&config.empty_format, &[("num", num), ("label", &label)]);
+    }
+    let symbols = match &config.dedup {
+        Some(dedup) => dedup_symbols(dedup, symbols),
+        None => symbols.to_vec(),
+    };
+    let icons = symbols
+        .iter()
+        .map(|symbol| format!("{}{symbol}{}", config.prefix, config.suffix))
+        .collect::<Vec<_>>()
+        .join(&config.separator);
+    render_template(
+        &config.format,
+        &[("num", num), ("label", &label), ("icons", &icons)],
+    )
+}
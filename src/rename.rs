@@ -0,0 +1,139 @@
+use std::collections::HashSet;
+
+use log::warn;
+
+/// Renames workspace `id` from `old` to `new`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename<T> {
+    pub id: T,
+    pub old: String,
+    pub new: String,
+}
+
+/// Orders the renames so no workspace is renamed to a name that is still in use. `names` are
+/// the names of all workspaces, including the ones which are not renamed.
+///
+/// Renames to a name another workspace keeps, or which an earlier rename already claimed, are
+/// dropped. Cycles like swapping two names go through a temporary name.
+pub fn plan<T: Clone>(renames: Vec<Rename<T>>, names: &[String]) -> Vec<Rename<T>> {
+    let mut pending: Vec<Rename<T>> = renames.into_iter().filter(|r| r.old != r.new).collect();
+
+    // Drop renames until the final names are unique. A dropped workspace keeps its name, which
+    // might block another rename
+    loop {
+        let olds: HashSet<&String> = pending.iter().map(|r| &r.old).collect();
+        let mut taken: HashSet<String> = names
+            .iter()
+            .filter(|n| !olds.contains(n))
+            .cloned()
+            .collect();
+        let (accepted, dropped): (Vec<_>, Vec<_>) = pending
+            .iter()
+            .cloned()
+            .partition(|r| taken.insert(r.new.clone()));
+        if dropped.is_empty() {
+            break;
+        }
+        for r in dropped {
+            warn!(
+                "Not renaming workspace {} to {} since the name is already taken",
+                r.old, r.new
+            );
+        }
+        pending = accepted;
+    }
+
+    let mut taken: HashSet<String> = names.iter().cloned().collect();
+    let mut steps = vec![];
+    while !pending.is_empty() {
+        let index = match pending.iter().position(|r| !taken.contains(&r.new)) {
+            Some(index) => index,
+            None => {
+                // Every remaining target is the current name of another remaining workspace, so
+                // they form cycles. Moving one out of the way breaks its cycle
+                let mut temporary = format!("{}_", pending[0].old);
+                while taken.contains(&temporary) {
+                    temporary.push('_');
+                }
+                let rename = &mut pending[0];
+                taken.insert(temporary.clone());
+                steps.push(Rename {
+                    id: rename.id.clone(),
+                    old: std::mem::replace(&mut rename.old, temporary.clone()),
+                    new: temporary,
+                });
+                taken.remove(&steps.last().unwrap().old);
+                continue;
+            }
+        };
+        let rename = pending.remove(index);
+        taken.remove(&rename.old);
+        taken.insert(rename.new.clone());
+        steps.push(rename);
+    }
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan_names(renames: &[(&str, &str)], names: &[&str]) -> Vec<(usize, String, String)> {
+        let renames = renames
+            .iter()
+            .enumerate()
+            .map(|(id, (old, new))| Rename {
+                id,
+                old: old.to_string(),
+                new: new.to_string(),
+            })
+            .collect();
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        plan(renames, &names)
+            .into_iter()
+            .map(|r| (r.id, r.old, r.new))
+            .collect()
+    }
+
+    fn steps(steps: &[(usize, &str, &str)]) -> Vec<(usize, String, String)> {
+        steps
+            .iter()
+            .map(|(id, old, new)| (*id, old.to_string(), new.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn skips_unchanged() {
+        let planned = plan_names(&[("1", "1"), ("2", "2: A")], &["1", "2"]);
+        assert_eq!(planned, steps(&[(1, "2", "2: A")]));
+    }
+
+    #[test]
+    fn orders_chains() {
+        let planned = plan_names(&[("a", "b"), ("b", "c"), ("c", "d")], &["a", "b", "c"]);
+        assert_eq!(
+            planned,
+            steps(&[(2, "c", "d"), (1, "b", "c"), (0, "a", "b")])
+        );
+    }
+
+    #[test]
+    fn breaks_cycles() {
+        let planned = plan_names(&[("a", "b"), ("b", "a")], &["a", "b", "a_"]);
+        assert_eq!(
+            planned,
+            steps(&[(0, "a", "a__"), (1, "b", "a"), (0, "a__", "b")])
+        );
+    }
+
+    #[test]
+    fn drops_collisions() {
+        // "c" is kept by a workspace which is not renamed and "d" is claimed by the first rename.
+        // Since "b" is not renamed then, the rename to "b" is dropped as well
+        let planned = plan_names(
+            &[("a", "d"), ("b", "d"), ("e", "b"), ("f", "c")],
+            &["a", "b", "c", "e", "f"],
+        );
+        assert_eq!(planned, steps(&[(0, "a", "d")]));
+    }
+}
@@ -0,0 +1,279 @@
+//! Output for a Waybar `custom` module with `"return-type": "json"`. Every module shows one
+//! workspace, whose line is printed whenever it changes
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{error, info};
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt, stdout};
+use tokio::sync::watch;
+
+use crate::config::SwayNameManagerConfig;
+use crate::ipc::{self, Names};
+use crate::markup::escape;
+use crate::wm::WorkspaceState;
+
+/// Time to wait before subscribing again while no instance is running
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// A line as Waybar expects it
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+struct Line {
+    text: String,
+    tooltip: String,
+    class: Vec<String>,
+    alt: String,
+}
+
+/// Turns an application name into a CSS class, e.g. `org.gnome.Nautilus` into
+/// `app-org-gnome-nautilus`
+fn app_class(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("app-{name}")
+}
+
+/// Waybar renders the text and tooltip as markup. `markup` tells whether the name is markup
+/// already
+fn line(state: &WorkspaceState, markup: bool) -> Line {
+    let mut class: Vec<String> = [
+        ("focused", state.focused),
+        ("urgent", state.urgent),
+        ("fullscreen", state.fullscreen),
+    ]
+    .into_iter()
+    .filter(|(_, set)| *set)
+    .map(|(name, _)| name.to_string())
+    .collect();
+    for window in &state.windows {
+        if let Some(name) = window.names().first() {
+            let app = app_class(name);
+            if !class.contains(&app) {
+                class.push(app);
+            }
+        }
+    }
+    Line {
+        text: if markup {
+            state.name.clone()
+        } else {
+            escape(&state.name)
+        },
+        tooltip: state
+            .windows
+            .iter()
+            .filter_map(|window| window.title.as_deref())
+            .map(escape)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        class,
+        alt: state.num.to_string(),
+    }
+}
+
+/// The line of workspace `num`. It is empty if the workspace does not exist, so Waybar hides the
+/// module
+fn workspace_line<'a>(
+    workspaces: impl IntoIterator<Item = &'a WorkspaceState>,
+    num: i32,
+    markup: bool,
+) -> Line {
+    match workspaces.into_iter().find(|state| state.num == num) {
+        Some(state) => line(state, markup),
+        None => Line {
+            text: String::new(),
+            tooltip: String::new(),
+            class: vec!["empty".to_string()],
+            alt: num.to_string(),
+        },
+    }
+}
+
+/// Prints `line` unless it is the one printed last
+async fn print(
+    out: &mut (impl AsyncWrite + Unpin),
+    printed: &mut Option<Line>,
+    line: Line,
+) -> Result<()> {
+    if printed.as_ref() == Some(&line) {
+        return Ok(());
+    }
+    out.write_all(format!("{}\n", serde_json::to_string(&line)?).as_bytes())
+        .await?;
+    out.flush().await?;
+    *printed = Some(line);
+    Ok(())
+}
+
+/// Prints the line of workspace `num` whenever it changed until the managers are gone. Used by
+/// the instance renaming the workspaces with `--output both`
+pub async fn run(
+    mut workspaces: watch::Receiver<BTreeMap<i32, WorkspaceState>>,
+    num: i32,
+    config: Arc<RwLock<SwayNameManagerConfig>>,
+) -> Result<()> {
+    let mut printed = None;
+    let mut stdout = stdout();
+    loop {
+        let markup = config.read().unwrap().uses_markup();
+        let line = workspace_line(workspaces.borrow_and_update().values(), num, markup);
+        print(&mut stdout, &mut printed, line).await?;
+        if workspaces.changed().await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Prints the line of workspace `num` to `out` whenever it changed, as reported by the instance
+/// listening on `socket_path`. Waybar modules share the running instance this way instead of
+/// talking to the window manager themselves. While no instance is running the line is empty and
+/// subscribing is retried
+pub async fn subscribe(
+    socket_path: &Path,
+    num: i32,
+    out: &mut (impl AsyncWrite + Unpin),
+) -> Result<()> {
+    let mut printed = None;
+    let mut connected = true;
+    loop {
+        match ipc::subscribe(socket_path).await {
+            Ok(mut replies) => {
+                connected = true;
+                while let Ok(Some(reply)) = replies.next_line().await {
+                    let names: Names = serde_json::from_str(&reply)?;
+                    let states = names.workspaces.iter().map(|w| &w.state);
+                    print(out, &mut printed, workspace_line(states, num, names.markup)).await?;
+                }
+                info!("The running instance closed the connection");
+            }
+            // Logged once, not on every retry
+            Err(err) if connected => {
+                error!("{err}");
+                connected = false;
+            }
+            Err(_) => {}
+        }
+        print(out, &mut printed, workspace_line([], num, false)).await?;
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    use super::*;
+    use crate::ipc::Daemon;
+    use crate::wm::{Output, WindowInfo};
+
+    fn state(num: i32, windows: &[(&str, &str)]) -> WorkspaceState {
+        WorkspaceState {
+            num,
+            name: num.to_string(),
+            focused: false,
+            urgent: false,
+            fullscreen: false,
+            windows: windows
+                .iter()
+                .map(|(app_id, title)| WindowInfo {
+                    app_id: Some(app_id.to_string()),
+                    title: Some(title.to_string()),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn line_contains_state_and_apps() {
+        let mut state = state(
+            2,
+            &[
+                ("org.gnome.Nautilus", "Home"),
+                ("kitty", "zsh"),
+                ("kitty", "vim <main.rs>"),
+            ],
+        );
+        state.name = "2: N|K&K".to_string();
+        state.focused = true;
+        state.fullscreen = true;
+        assert_eq!(
+            serde_json::to_value(line(&state, false)).unwrap(),
+            json!({
+                "text": "2: N|K&amp;K",
+                "tooltip": "Home\nzsh\nvim &lt;main.rs&gt;",
+                "class": ["focused", "fullscreen", "app-org-gnome-nautilus", "app-kitty"],
+                "alt": "2",
+            })
+        );
+    }
+
+    #[test]
+    fn selected_workspace_only() {
+        let workspaces = [state(1, &[]), state(2, &[("a", "")])];
+        let selected = workspace_line(&workspaces, 2, false);
+        assert_eq!(
+            (selected.alt.as_str(), selected.class),
+            ("2", vec!["app-a".to_string()])
+        );
+
+        let missing = workspace_line(&workspaces, 3, false);
+        assert_eq!(missing.text, "");
+        assert_eq!(missing.class, ["empty"]);
+    }
+
+    #[tokio::test]
+    async fn subscribes_to_the_running_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("swayautonames.sock");
+        let output = Output::default();
+        output
+            .workspaces
+            .send_replace(BTreeMap::from([(10, state(1, &[("a", "")]))]));
+        let daemon = Daemon {
+            config: Default::default(),
+            config_path: None,
+            resync: watch::Sender::new(()),
+            output: output.clone(),
+            shutdown: watch::Sender::new(()),
+        };
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        let client_path = path.clone();
+        tokio::spawn(async move { subscribe(&client_path, 1, &mut writer).await });
+        let mut lines = BufReader::new(reader).lines();
+        let mut next_line = async || -> Value {
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap()
+        };
+        // Empty until the instance is started
+        assert_eq!(next_line().await["class"], json!(["empty"]));
+
+        let server_path = path.clone();
+        tokio::spawn(async move { daemon.serve(&server_path).await });
+        assert_eq!(next_line().await["text"], "1");
+        output
+            .workspaces
+            .send_modify(|workspaces| workspaces.get_mut(&10).unwrap().name = "1: A".into());
+        assert_eq!(next_line().await["text"], "1: A");
+        // Other workspaces don't print anything
+        output.workspaces.send_modify(|workspaces| {
+            workspaces.insert(20, state(2, &[]));
+        });
+        output
+            .workspaces
+            .send_modify(|workspaces| workspaces.get_mut(&10).unwrap().urgent = true);
+        assert_eq!(next_line().await["class"], json!(["urgent", "app-a"]));
+    }
+}
@@ -0,0 +1,355 @@
+//! Helpers shared by the backends talking to a real window manager. The mock does not need them
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use futures_util::{Stream, StreamExt, stream::Fuse};
+use log::error;
+use tokio::sync::watch;
+use tokio::time::{Instant, timeout_at};
+
+use crate::config::SwayNameManagerConfig;
+use crate::naming;
+use crate::wm::{Output, WorkspaceState};
+
+impl Output {
+    /// Workspaces focused after the last update. They have to be updated once the focus moves away
+    pub fn focused(&self) -> HashSet<i32> {
+        self.workspaces
+            .borrow()
+            .iter()
+            .filter(|(_, state)| state.focused)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+/// The workspaces an event changed. Windows are given by their id `W`, since the workspace they
+/// were on before might already be gone from the window manager's state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Affected<W: Eq + Hash> {
+    /// Every workspace has to be updated, e.g. after a reconnect
+    pub all: bool,
+    pub workspaces: HashSet<i32>,
+    pub windows: HashSet<W>,
+}
+
+impl<W: Eq + Hash> Default for Affected<W> {
+    fn default() -> Self {
+        Self {
+            all: false,
+            workspaces: HashSet::new(),
+            windows: HashSet::new(),
+        }
+    }
+}
+
+impl<W: Eq + Hash> Affected<W> {
+    pub fn all() -> Self {
+        Self {
+            all: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn workspace(id: i32) -> Self {
+        Self {
+            workspaces: HashSet::from([id]),
+            ..Default::default()
+        }
+    }
+
+    pub fn window(id: W) -> Self {
+        Self {
+            windows: HashSet::from([id]),
+            ..Default::default()
+        }
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.all |= other.all;
+        self.workspaces.extend(other.workspaces);
+        self.windows.extend(other.windows);
+    }
+
+    /// The workspaces to update. A window affects the workspace it was on before, which is looked
+    /// up in `previous`, and the one it is on now, looked up in `current`
+    pub fn resolve(&self, previous: &HashMap<W, i32>, current: &HashMap<W, i32>) -> HashSet<i32> {
+        let mut workspaces = self.workspaces.clone();
+        for window in &self.windows {
+            workspaces.extend(previous.get(window));
+            workspaces.extend(current.get(window));
+        }
+        workspaces
+    }
+}
+
+/// Names of the workspaces before they were renamed the first time
+#[derive(Debug, Default)]
+struct OriginalNames(Mutex<HashMap<i32, String>>);
+
+impl OriginalNames {
+    /// Remembers the names of the workspaces seen for the first time and forgets the ones that
+    /// are gone, so a new workspace with the same id doesn't get the name of an old one.
+    /// `workspaces` are all current workspaces as id, number and name. Icons rendered by a
+    /// previous run are stripped, so only the number and label are restored
+    fn update(&self, config: &SwayNameManagerConfig, workspaces: &[(i32, String, String)]) {
+        let mut names = self.0.lock().unwrap();
+        for (id, num, name) in workspaces {
+            names
+                .entry(*id)
+                .or_insert_with(|| naming::bare_name(config, name, num));
+        }
+        names.retain(|id, _| workspaces.iter().any(|(other, _, _)| other == id));
+    }
+
+    fn get(&self, id: i32) -> Option<String> {
+        self.0.lock().unwrap().get(&id).cloned()
+    }
+}
+
+/// What a backend keeps between updates. Windows are identified by `W`
+#[derive(Debug)]
+pub struct Tracker<W> {
+    /// Workspace id of every window as of the last update
+    windows: Mutex<HashMap<W, i32>>,
+    original_names: OriginalNames,
+    pub output: Output,
+}
+
+impl<W: Eq + Hash> Tracker<W> {
+    pub fn new(output: Output) -> Self {
+        Self {
+            windows: Default::default(),
+            original_names: Default::default(),
+            output,
+        }
+    }
+
+    pub fn original_name(&self, id: i32) -> Option<String> {
+        self.original_names.get(id)
+    }
+
+    /// The part of an update all backends share. `workspaces` are all current workspaces as id,
+    /// number and name and `windows` maps every window to the id of its workspace. Publishes the
+    /// states `state` returns for the workspaces `affected` by the events and returns their new
+    /// names. Nothing is returned while renaming is paused
+    pub fn update(
+        &self,
+        config: &SwayNameManagerConfig,
+        affected: &Affected<W>,
+        workspaces: &[(i32, String, String)],
+        windows: HashMap<W, i32>,
+        mut state: impl FnMut(i32) -> Option<WorkspaceState>,
+    ) -> Vec<(i32, String)> {
+        self.original_names.update(config, workspaces);
+        let mut ids = affected.resolve(&self.windows.lock().unwrap(), &windows);
+        *self.windows.lock().unwrap() = windows;
+        // The focus might have moved away from them
+        ids.extend(self.output.focused());
+        let states: Vec<(i32, WorkspaceState)> = workspaces
+            .iter()
+            .map(|(id, _, _)| *id)
+            .filter(|id| affected.all || ids.contains(id))
+            .filter_map(|id| Some((id, state(id)?)))
+            .collect();
+        let names = states
+            .iter()
+            .map(|(id, state)| (*id, state.name.clone()))
+            .collect();
+        let existing = workspaces.iter().map(|(id, _, _)| *id).collect();
+        self.output.publish(&existing, states);
+        if self.output.renames() { names } else { vec![] }
+    }
+}
+
+/// Coalesces the workspaces affected by bursts of events, so they are renamed once
+pub struct Debouncer<S> {
+    events: Pin<Box<Fuse<S>>>,
+    /// Changes when the config was reloaded
+    resync: watch::Receiver<()>,
+    config: Arc<RwLock<SwayNameManagerConfig>>,
+}
+
+impl<W: Eq + Hash, E, S: Stream<Item = Result<Affected<W>, E>>> Debouncer<S> {
+    pub fn new(
+        events: S,
+        mut resync: watch::Receiver<()>,
+        config: Arc<RwLock<SwayNameManagerConfig>>,
+    ) -> Self {
+        // Reloads before this point are covered by the initial update
+        resync.borrow_and_update();
+        Self {
+            events: Box::pin(events.fuse()),
+            resync,
+            config,
+        }
+    }
+
+    async fn next_affected(&mut self) -> Option<Result<Affected<W>, E>> {
+        tokio::select! {
+            affected = self.events.next() => affected,
+            Ok(()) = self.resync.changed() => Some(Ok(Affected::all())),
+        }
+    }
+
+    /// Waits for an event affecting any workspace and merges all events arriving within
+    /// `debounce_ms` after it. Returns `None` once the events ended. Errors are returned right
+    /// away, since the caller resyncs after reconnecting anyway
+    pub async fn next(&mut self) -> Option<Result<Affected<W>, E>> {
+        let mut affected = loop {
+            match self.next_affected().await? {
+                Ok(affected) if affected != Affected::default() => break affected,
+                Ok(_) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        };
+        let debounce = Duration::from_millis(self.config.read().unwrap().debounce_ms);
+        let deadline = Instant::now() + debounce;
+        // The end of the events is reported by the next call
+        while let Ok(Some(next)) = timeout_at(deadline, self.next_affected()).await {
+            match next {
+                Ok(next) => affected.merge(next),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        Some(Ok(affected))
+    }
+}
+
+/// Time tests wait for an expected change before failing
+#[cfg(test)]
+pub const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time to wait before the first reconnect. It doubles with every failed attempt
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// Failed attempts in a row after which the window manager is considered gone
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+/// Delays between attempts to reconnect to a window manager
+#[derive(Debug)]
+pub struct Backoff {
+    delay: Duration,
+    attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            delay: RECONNECT_DELAY,
+            attempts: 0,
+        }
+    }
+}
+
+impl Backoff {
+    /// Called once connected, so the next reconnect starts with the shortest delay again
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Waits before the next attempt. Fails once too many attempts in a row failed
+    pub async fn wait(&mut self) -> anyhow::Result<()> {
+        if self.attempts >= MAX_RECONNECT_ATTEMPTS {
+            return Err(anyhow!("Giving up after {} attempts", self.attempts));
+        }
+        tokio::time::sleep(self.delay).await;
+        self.attempts += 1;
+        self.delay = (self.delay * 2).min(MAX_RECONNECT_DELAY);
+        Ok(())
+    }
+}
+
+/// A backend holding a connection to its window manager
+pub trait Connected {
+    /// Runs until the connection is lost. `backoff` is reset once connected
+    async fn run(&self, backoff: &mut Backoff) -> anyhow::Result<()>;
+}
+
+/// Runs `backend` and reconnects with a backoff whenever the connection is lost. Returns once
+/// reconnecting failed too often
+pub async fn reconnect(name: &str, backend: &impl Connected) -> anyhow::Result<()> {
+    let mut backoff = Backoff::default();
+    loop {
+        if let Err(err) = backend.run(&mut backoff).await {
+            error!("Reconnecting because {name} returned with error: {err}");
+        }
+        backoff.wait().await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream;
+    use serde_json::json;
+
+    use super::*;
+
+    fn config(debounce_ms: u64) -> Arc<RwLock<SwayNameManagerConfig>> {
+        let config = serde_json::from_value(json!({ "debounce_ms": debounce_ms }));
+        Arc::new(RwLock::new(config.unwrap()))
+    }
+
+    #[test]
+    fn original_names_strip_icons_and_forget_removed_workspaces() {
+        let config = config(0).read().unwrap().clone();
+        let names = OriginalNames::default();
+        let workspace = |id: i32, name: &str| (id, id.to_string(), name.to_string());
+        names.update(
+            &config,
+            &[
+                workspace(1, "1: F|K"),
+                workspace(2, "2:mail: F"),
+                workspace(3, "chat"),
+            ],
+        );
+        assert_eq!(names.get(1).as_deref(), Some("1"));
+        assert_eq!(names.get(2).as_deref(), Some("2:mail"));
+        assert_eq!(names.get(3).as_deref(), Some("chat"));
+
+        // Names are only recorded the first time
+        names.update(&config, &[workspace(1, "1:web")]);
+        assert_eq!(names.get(1).as_deref(), Some("1"));
+        assert_eq!(names.get(2), None);
+        names.update(&config, &[workspace(2, "2: K")]);
+        assert_eq!(names.get(2).as_deref(), Some("2"));
+    }
+
+    #[tokio::test]
+    async fn debouncer_merges_burst() {
+        let (_resync, receiver) = watch::channel(());
+        let events = stream::iter([
+            Ok::<_, anyhow::Error>(Affected::window(1)),
+            Ok(Affected::default()),
+            Ok(Affected {
+                workspaces: HashSet::from([2]),
+                ..Default::default()
+            }),
+            Ok(Affected::window(3)),
+        ]);
+        let mut debouncer = Debouncer::new(events, receiver, config(1000));
+        let affected = debouncer.next().await.unwrap().unwrap();
+        assert_eq!(affected.workspaces, HashSet::from([2]));
+        assert_eq!(affected.windows, HashSet::from([1, 3]));
+        assert!(debouncer.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn debouncer_resyncs_on_reload() {
+        let (resync, receiver) = watch::channel(());
+        // Reloads before creating the debouncer are ignored
+        resync.send_replace(());
+        let mut debouncer = Debouncer::new(
+            stream::pending::<anyhow::Result<Affected<i32>>>(),
+            receiver,
+            config(0),
+        );
+        resync.send_replace(());
+        assert_eq!(debouncer.next().await.unwrap().unwrap(), Affected::all());
+    }
+}
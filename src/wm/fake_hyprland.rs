@@ -0,0 +1,264 @@
+//! A fake Hyprland instance listening on `.socket.sock` and `.socket2.sock` in a temporary
+//! directory. It answers `j/workspaces`, `j/activeworkspace` and `j/clients`, records every
+//! dispatch and `[[BATCH]]` request and pushes scripted events. `renameworkspace` dispatches are
+//! applied like Hyprland would.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::{Value, json};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::{broadcast, watch},
+    time::timeout,
+};
+
+use crate::wm::TEST_TIMEOUT;
+
+struct State {
+    workspaces: Vec<Value>,
+    /// Id of the focused workspace
+    active_workspace: Value,
+    clients: Vec<Value>,
+}
+
+pub struct FakeHyprland {
+    dir: TempDir,
+    state: Arc<Mutex<State>>,
+    /// All dispatch and batch requests received so far
+    dispatches: watch::Sender<Vec<String>>,
+    /// Event lines. `None` closes all event sockets
+    events: broadcast::Sender<Option<String>>,
+    /// Number of event sockets connected so far
+    event_connections: watch::Sender<usize>,
+}
+
+impl FakeHyprland {
+    pub async fn start(workspaces: Vec<Value>, clients: Vec<Value>) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let command_listener = UnixListener::bind(dir.path().join(".socket.sock")).unwrap();
+        let event_listener = UnixListener::bind(dir.path().join(".socket2.sock")).unwrap();
+        let state = Arc::new(Mutex::new(State {
+            active_workspace: workspaces[0]["id"].clone(),
+            workspaces,
+            clients,
+        }));
+        let dispatches = watch::Sender::new(vec![]);
+        let (events, _) = broadcast::channel(64);
+        let event_connections = watch::Sender::new(0);
+        let server_state = state.clone();
+        let server_dispatches = dispatches.clone();
+        let server_events = events.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = command_listener.accept().await {
+                tokio::spawn(handle_command(
+                    stream,
+                    server_state.clone(),
+                    server_dispatches.clone(),
+                    server_events.clone(),
+                ));
+            }
+        });
+        let server_events = events.clone();
+        let server_event_connections = event_connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = event_listener.accept().await {
+                let receiver = server_events.subscribe();
+                server_event_connections.send_modify(|count| *count += 1);
+                tokio::spawn(forward_events(stream, receiver));
+            }
+        });
+        Self {
+            dir,
+            state,
+            dispatches,
+            events,
+            event_connections,
+        }
+    }
+
+    /// The directory containing the sockets
+    pub fn socket_dir(&self) -> PathBuf {
+        self.dir.path().to_path_buf()
+    }
+
+    /// All dispatch and batch requests received so far
+    pub fn dispatches(&self) -> Vec<String> {
+        self.dispatches.borrow().clone()
+    }
+
+    pub fn workspace_names(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .workspaces
+            .iter()
+            .map(|w| w["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    pub fn set_clients(&self, clients: Vec<Value>) {
+        self.state.lock().unwrap().clients = clients;
+    }
+
+    /// Focuses the workspace. The first workspace is focused initially
+    pub fn set_active_workspace(&self, id: i32) {
+        self.state.lock().unwrap().active_workspace = json!(id);
+    }
+
+    /// Pushes an event like `openwindow>>address,workspace,class,title`
+    pub fn send_event(&self, event: &str) {
+        let _ = self.events.send(Some(event.to_string()));
+    }
+
+    /// Closes all connected event sockets. New connections are still accepted
+    pub fn close_event_sockets(&self) {
+        let _ = self.events.send(None);
+    }
+
+    /// Waits until at least `count` dispatches were received. Panics after [`TEST_TIMEOUT`]
+    pub async fn wait_for_dispatches(&self, count: usize) -> Vec<String> {
+        let mut dispatches = self.dispatches.subscribe();
+        match timeout(TEST_TIMEOUT, dispatches.wait_for(|d| d.len() >= count)).await {
+            Ok(dispatches) => dispatches.unwrap().clone(),
+            Err(_) => panic!(
+                "Expected {count} dispatches but got {:?}",
+                self.dispatches()
+            ),
+        }
+    }
+
+    /// Waits until at least `count` event sockets were connected. Panics after [`TEST_TIMEOUT`]
+    pub async fn wait_for_event_connections(&self, count: usize) {
+        let mut connections = self.event_connections.subscribe();
+        if timeout(TEST_TIMEOUT, connections.wait_for(|c| *c >= count))
+            .await
+            .is_err()
+        {
+            panic!("Expected {count} event connections");
+        }
+    }
+}
+
+/// Hyprland reads a single request per connection and closes it after replying
+async fn handle_command(
+    mut stream: UnixStream,
+    state: Arc<Mutex<State>>,
+    dispatches: watch::Sender<Vec<String>>,
+    events: broadcast::Sender<Option<String>>,
+) {
+    let mut buffer = [0_u8; 8192];
+    let Ok(length) = stream.read(&mut buffer).await else {
+        return;
+    };
+    let request = String::from_utf8_lossy(&buffer[..length]).to_string();
+    let reply = {
+        let mut state = state.lock().unwrap();
+        match request.as_str() {
+            "j/workspaces" => Value::Array(state.workspaces.clone()).to_string(),
+            "j/activeworkspace" => state
+                .workspaces
+                .iter()
+                .find(|w| w["id"] == state.active_workspace)
+                .cloned()
+                .unwrap_or_default()
+                .to_string(),
+            "j/clients" => Value::Array(state.clients.clone()).to_string(),
+            _ => {
+                // Batches reply once per command
+                let commands = match request.strip_prefix("[[BATCH]]") {
+                    Some(batch) => batch.split(';').collect(),
+                    None => vec![request.as_str()],
+                };
+                let reply = commands
+                    .into_iter()
+                    .map(|command| {
+                        match command.trim_start_matches("j/").strip_prefix("dispatch ") {
+                            Some(dispatch) => run_dispatch(&mut state, &events, dispatch),
+                            None => format!("unknown request {command}"),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                // Recorded once applied, so waiting for a dispatch sees its effect
+                dispatches.send_modify(|dispatches| dispatches.push(request.clone()));
+                reply
+            }
+        }
+    };
+    let _ = stream.write_all(reply.as_bytes()).await;
+}
+
+fn run_dispatch(
+    state: &mut State,
+    events: &broadcast::Sender<Option<String>>,
+    dispatch: &str,
+) -> String {
+    let Some((id, name)) = dispatch
+        .strip_prefix("renameworkspace ")
+        .and_then(|args| args.split_once(' '))
+        .and_then(|(id, name)| Some((id.parse::<i64>().ok()?, name)))
+    else {
+        return format!("Invalid dispatcher {dispatch}");
+    };
+    let Some(workspace) = state.workspaces.iter_mut().find(|w| w["id"] == id) else {
+        return "Invalid workspace".to_string();
+    };
+    workspace["name"] = json!(name);
+    let _ = events.send(Some(format!("renameworkspace>>{id},{name}")));
+    "ok".to_string()
+}
+
+async fn forward_events(mut stream: UnixStream, mut events: broadcast::Receiver<Option<String>>) {
+    while let Ok(Some(event)) = events.recv().await {
+        if stream
+            .write_all(format!("{event}\n").as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+pub fn workspace(id: i32, name: &str) -> Value {
+    json!({
+        "id": id,
+        "name": name,
+        "monitor": "FAKE-1",
+        "monitorID": 0,
+        "windows": 0,
+        "hasfullscreen": false,
+        "lastwindow": "0x0",
+        "lastwindowtitle": "",
+    })
+}
+
+/// A wayland client. `x` is its horizontal position
+pub fn client(address: &str, workspace_id: i32, x: i16, class: &str, title: &str) -> Value {
+    json!({
+        "address": format!("0x{address}"),
+        "at": [x, 0],
+        "size": [100, 100],
+        "workspace": { "id": workspace_id, "name": workspace_id.to_string() },
+        "floating": false,
+        "fullscreen": 0,
+        "fullscreenClient": 0,
+        "monitor": 0,
+        "initialClass": class,
+        "class": class,
+        "initialTitle": title,
+        "title": title,
+        "pid": 1,
+        "xwayland": false,
+        "pinned": false,
+        "grouped": [],
+        "mapped": true,
+        "swallowing": null,
+        "focusHistoryID": 0,
+    })
+}
@@ -0,0 +1,237 @@
+//! A fake niri instance listening on a socket in a temporary directory. It answers `Workspaces`
+//! and `Windows`, streams events and applies and records the `SetWorkspaceName` and
+//! `UnsetWorkspaceName` actions. Like niri, it refuses names already in use.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::{Value, json};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{broadcast, watch},
+    time::timeout,
+};
+
+use crate::wm::TEST_TIMEOUT;
+
+struct State {
+    workspaces: Vec<Value>,
+    windows: Vec<Value>,
+}
+
+pub struct FakeNiri {
+    dir: TempDir,
+    state: Arc<Mutex<State>>,
+    /// All actions applied so far, like `SetWorkspaceName 1 1: K` or `UnsetWorkspaceName 2`
+    actions: watch::Sender<Vec<String>>,
+    /// Event lines sent to every event stream
+    events: broadcast::Sender<String>,
+    /// Number of event streams started so far
+    event_streams: watch::Sender<usize>,
+}
+
+impl FakeNiri {
+    pub async fn start(workspaces: Vec<Value>, windows: Vec<Value>) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = UnixListener::bind(dir.path().join("niri.sock")).unwrap();
+        let state = Arc::new(Mutex::new(State {
+            workspaces,
+            windows,
+        }));
+        let actions = watch::Sender::new(vec![]);
+        let (events, _) = broadcast::channel(64);
+        let event_streams = watch::Sender::new(0);
+        let server_state = state.clone();
+        let server_actions = actions.clone();
+        let server_events = events.clone();
+        let server_event_streams = event_streams.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_client(
+                    stream,
+                    server_state.clone(),
+                    server_actions.clone(),
+                    server_events.clone(),
+                    server_event_streams.clone(),
+                ));
+            }
+        });
+        Self {
+            dir,
+            state,
+            actions,
+            events,
+            event_streams,
+        }
+    }
+
+    pub fn socket_path(&self) -> PathBuf {
+        self.dir.path().join("niri.sock")
+    }
+
+    /// All actions applied so far
+    pub fn actions(&self) -> Vec<String> {
+        self.actions.borrow().clone()
+    }
+
+    /// The names of the workspaces. Unnamed ones are `None`
+    pub fn workspace_names(&self) -> Vec<Option<String>> {
+        self.state
+            .lock()
+            .unwrap()
+            .workspaces
+            .iter()
+            .map(|w| w["name"].as_str().map(str::to_string))
+            .collect()
+    }
+
+    /// Replaces the windows and sends `WindowsChanged`
+    pub fn set_windows(&self, windows: Vec<Value>) {
+        self.state.lock().unwrap().windows = windows.clone();
+        let _ = self
+            .events
+            .send(json!({ "WindowsChanged": { "windows": windows } }).to_string());
+    }
+
+    /// Waits until at least `count` actions were applied. Panics after [`TEST_TIMEOUT`]
+    pub async fn wait_for_actions(&self, count: usize) -> Vec<String> {
+        let mut actions = self.actions.subscribe();
+        match timeout(TEST_TIMEOUT, actions.wait_for(|a| a.len() >= count)).await {
+            Ok(actions) => actions.unwrap().clone(),
+            Err(_) => panic!("Expected {count} actions but got {:?}", self.actions()),
+        }
+    }
+
+    /// Waits until at least `count` event streams were started. Panics after [`TEST_TIMEOUT`]
+    pub async fn wait_for_event_streams(&self, count: usize) {
+        let mut streams = self.event_streams.subscribe();
+        if timeout(TEST_TIMEOUT, streams.wait_for(|s| *s >= count))
+            .await
+            .is_err()
+        {
+            panic!("Expected {count} event streams");
+        }
+    }
+}
+
+/// niri reads a single request per connection. `EventStream` keeps the connection open for the
+/// events
+async fn handle_client(
+    stream: UnixStream,
+    state: Arc<Mutex<State>>,
+    actions: watch::Sender<Vec<String>>,
+    events: broadcast::Sender<String>,
+    event_streams: watch::Sender<usize>,
+) {
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    if stream.read_line(&mut line).await.is_err() {
+        return;
+    }
+    let Ok(request) = serde_json::from_str::<Value>(&line) else {
+        return;
+    };
+    if request == "EventStream" {
+        let events = events.subscribe();
+        event_streams.send_modify(|count| *count += 1);
+        forward_events(stream.into_inner(), events).await;
+        return;
+    }
+    let reply = {
+        let mut state = state.lock().unwrap();
+        if request == "Workspaces" {
+            json!({ "Ok": { "Workspaces": state.workspaces } })
+        } else if request == "Windows" {
+            json!({ "Ok": { "Windows": state.windows } })
+        } else {
+            match run_action(&mut state, &request["Action"]) {
+                Ok(action) => {
+                    let _ = events.send(
+                        json!({ "WorkspacesChanged": { "workspaces": state.workspaces } })
+                            .to_string(),
+                    );
+                    // Recorded once applied, so waiting for an action sees its effect
+                    actions.send_modify(|actions| actions.push(action));
+                    json!({ "Ok": "Handled" })
+                }
+                Err(err) => json!({ "Err": err }),
+            }
+        }
+    };
+    let _ = stream
+        .get_mut()
+        .write_all(format!("{reply}\n").as_bytes())
+        .await;
+}
+
+/// Returns the action as recorded
+fn run_action(state: &mut State, action: &Value) -> Result<String, String> {
+    let (id, name) = if let Some(set) = action.get("SetWorkspaceName") {
+        (&set["workspace"]["Id"], set["name"].as_str())
+    } else if let Some(unset) = action.get("UnsetWorkspaceName") {
+        (&unset["reference"]["Id"], None)
+    } else {
+        return Err(format!("Unknown request {action}"));
+    };
+    if let Some(name) = name
+        && state.workspaces.iter().any(|w| w["name"] == name)
+    {
+        return Err(format!("Workspace name {name} is in use"));
+    }
+    let Some(workspace) = state.workspaces.iter_mut().find(|w| w["id"] == *id) else {
+        return Err("Workspace not found".to_string());
+    };
+    workspace["name"] = json!(name);
+    Ok(match name {
+        Some(name) => format!("SetWorkspaceName {id} {name}"),
+        None => format!("UnsetWorkspaceName {id}"),
+    })
+}
+
+async fn forward_events(mut stream: UnixStream, mut events: broadcast::Receiver<String>) {
+    if stream.write_all(b"{\"Ok\":\"Handled\"}\n").await.is_err() {
+        return;
+    }
+    while let Ok(event) = events.recv().await {
+        if stream
+            .write_all(format!("{event}\n").as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Workspace number `idx` on `output`
+pub fn workspace(id: u64, idx: u8, name: Option<&str>, output: &str) -> Value {
+    json!({
+        "id": id,
+        "idx": idx,
+        "name": name,
+        "output": output,
+        "is_urgent": false,
+        "is_active": idx == 1,
+        "is_focused": false,
+        "active_window_id": null,
+    })
+}
+
+/// A tiled window in `column`
+pub fn window(id: u64, workspace_id: u64, column: usize, app_id: &str, title: &str) -> Value {
+    json!({
+        "id": id,
+        "title": title,
+        "app_id": app_id,
+        "pid": 1,
+        "workspace_id": workspace_id,
+        "is_focused": false,
+        "is_floating": false,
+        "is_urgent": false,
+        "layout": { "pos_in_scrolling_layout": [column, 1] },
+    })
+}
@@ -0,0 +1,356 @@
+//! A fake sway speaking the i3/sway IPC protocol on a temporary UNIX socket. It serves a canned
+//! tree, derives the workspaces from it, emits scripted events and records every command it
+//! receives. `rename workspace` commands are applied to the tree like sway would.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use regex::Regex;
+use serde_json::{Value, json};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::{broadcast, watch},
+    time::timeout,
+};
+
+use crate::wm::TEST_TIMEOUT;
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+const GET_TREE: u32 = 4;
+const EVENT_WORKSPACE: u32 = 0x8000_0000;
+const EVENT_WINDOW: u32 = 0x8000_0003;
+
+struct State {
+    tree: Value,
+    connections: usize,
+    tree_requests: usize,
+}
+
+pub struct FakeSway {
+    _dir: TempDir,
+    /// The socket path. This is what sway exports as SWAYSOCK
+    pub socket: PathBuf,
+    state: Arc<Mutex<State>>,
+    /// All commands received so far
+    commands: watch::Sender<Vec<String>>,
+    events: broadcast::Sender<(u32, Value)>,
+    /// Closes all connections
+    disconnects: broadcast::Sender<()>,
+}
+
+impl FakeSway {
+    pub async fn start(tree: Value) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("sway-ipc.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let state = Arc::new(Mutex::new(State {
+            tree,
+            connections: 0,
+            tree_requests: 0,
+        }));
+        let commands = watch::Sender::new(vec![]);
+        let (events, _) = broadcast::channel(64);
+        let (disconnects, _) = broadcast::channel(1);
+        let server_state = state.clone();
+        let server_commands = commands.clone();
+        let server_events = events.clone();
+        let server_disconnects = disconnects.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                server_state.lock().unwrap().connections += 1;
+                let client = handle_client(
+                    stream,
+                    server_state.clone(),
+                    server_commands.clone(),
+                    server_events.clone(),
+                );
+                let mut disconnect = server_disconnects.subscribe();
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = client => {}
+                        _ = disconnect.recv() => {}
+                    }
+                });
+            }
+        });
+        Self {
+            _dir: dir,
+            socket,
+            state,
+            commands,
+            events,
+            disconnects,
+        }
+    }
+
+    /// All commands received so far
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.borrow().clone()
+    }
+
+    /// Number of connections accepted so far
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    /// Number of `get_tree` requests received so far
+    pub fn tree_requests(&self) -> usize {
+        self.state.lock().unwrap().tree_requests
+    }
+
+    pub fn tree(&self) -> Value {
+        self.state.lock().unwrap().tree.clone()
+    }
+
+    pub fn set_tree(&self, tree: Value) {
+        self.state.lock().unwrap().tree = tree;
+    }
+
+    /// Names of all workspaces in tree order
+    pub fn workspace_names(&self) -> Vec<String> {
+        workspaces(&self.tree())
+            .iter()
+            .map(|w| w["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    pub fn send_window_event(&self, change: &str, container: Value) {
+        let _ = self.events.send((
+            EVENT_WINDOW,
+            json!({ "change": change, "container": container }),
+        ));
+    }
+
+    pub fn send_workspace_event(&self, change: &str, current: Value) {
+        let _ = self.events.send((
+            EVENT_WORKSPACE,
+            json!({ "change": change, "current": current, "old": null }),
+        ));
+    }
+
+    /// Closes all connections like a restarting sway. New connections are still accepted
+    pub fn disconnect(&self) {
+        let _ = self.disconnects.send(());
+    }
+
+    /// Waits until at least `count` commands were received. Panics after [`TEST_TIMEOUT`]
+    pub async fn wait_for_commands(&self, count: usize) -> Vec<String> {
+        let mut commands = self.commands.subscribe();
+        match timeout(TEST_TIMEOUT, commands.wait_for(|c| c.len() >= count)).await {
+            Ok(commands) => commands.unwrap().clone(),
+            Err(_) => panic!("Expected {count} commands but got {:?}", self.commands()),
+        }
+    }
+}
+
+async fn read_message(stream: &mut UnixStream) -> Option<(u32, Vec<u8>)> {
+    let mut header = [0_u8; 14];
+    stream.read_exact(&mut header).await.ok()?;
+    assert_eq!(&header[..6], MAGIC);
+    let length = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+    let mut payload = vec![0_u8; length as usize];
+    stream.read_exact(&mut payload).await.ok()?;
+    Some((message_type, payload))
+}
+
+async fn write_message(stream: &mut UnixStream, message_type: u32, payload: &Value) -> bool {
+    let payload = payload.to_string();
+    let message: Vec<u8> = MAGIC
+        .iter()
+        .copied()
+        .chain((payload.len() as u32).to_ne_bytes())
+        .chain(message_type.to_ne_bytes())
+        .chain(payload.bytes())
+        .collect();
+    stream.write_all(&message).await.is_ok()
+}
+
+async fn handle_client(
+    mut stream: UnixStream,
+    state: Arc<Mutex<State>>,
+    commands: watch::Sender<Vec<String>>,
+    events: broadcast::Sender<(u32, Value)>,
+) {
+    while let Some((message_type, payload)) = read_message(&mut stream).await {
+        let payload = String::from_utf8(payload).unwrap();
+        let reply = match message_type {
+            RUN_COMMAND => {
+                let reply = run_command(&state, &events, &payload);
+                // Recorded once applied, so waiting for a command sees its effect
+                commands.send_modify(|commands| commands.push(payload.clone()));
+                reply
+            }
+            GET_WORKSPACES => Value::Array(workspaces(&state.lock().unwrap().tree)),
+            GET_TREE => {
+                let mut state = state.lock().unwrap();
+                state.tree_requests += 1;
+                state.tree.clone()
+            }
+            SUBSCRIBE => {
+                let subscribed: Vec<String> = serde_json::from_str(&payload).unwrap();
+                let mut receiver = events.subscribe();
+                write_message(&mut stream, SUBSCRIBE, &json!({ "success": true })).await;
+                while let Ok((event_type, event)) = receiver.recv().await {
+                    let name = match event_type {
+                        EVENT_WORKSPACE => "workspace",
+                        _ => "window",
+                    };
+                    if subscribed.iter().any(|s| s == name)
+                        && !write_message(&mut stream, event_type, &event).await
+                    {
+                        break;
+                    }
+                }
+                return;
+            }
+            _ => panic!("Unsupported message type {message_type}"),
+        };
+        if !write_message(&mut stream, message_type, &reply).await {
+            return;
+        }
+    }
+}
+
+/// Executes a `;` separated command list. Only `rename workspace` is supported
+fn run_command(
+    state: &Mutex<State>,
+    events: &broadcast::Sender<(u32, Value)>,
+    payload: &str,
+) -> Value {
+    let rename =
+        Regex::new(r#"^rename workspace "((?:[^"\\]|\\.)*)" to "((?:[^"\\]|\\.)*)"$"#).unwrap();
+    let unquote = Regex::new(r"\\(.)").unwrap();
+    let mut state = state.lock().unwrap();
+    let mut outcomes = vec![];
+    for command in payload.split(';').map(str::trim) {
+        let Some(captures) = rename.captures(command) else {
+            outcomes
+                .push(json!({ "success": false, "parse_error": true, "error": "Unknown command" }));
+            continue;
+        };
+        let old = unquote.replace_all(&captures[1], "$1").to_string();
+        let new = unquote.replace_all(&captures[2], "$1").to_string();
+        let names: Vec<String> = workspaces(&state.tree)
+            .iter()
+            .map(|w| w["name"].as_str().unwrap().to_string())
+            .collect();
+        if names.contains(&new) {
+            outcomes.push(json!({
+                "success": false,
+                "parse_error": false,
+                "error": format!("Workspace \"{new}\" already exists"),
+            }));
+        } else if let Some(workspace) = find_workspace(&mut state.tree, &old) {
+            workspace["name"] = json!(new);
+            let _ = events.send((
+                EVENT_WORKSPACE,
+                json!({ "change": "rename", "current": workspace.clone(), "old": null }),
+            ));
+            outcomes.push(json!({ "success": true }));
+        } else {
+            outcomes.push(json!({
+                "success": false,
+                "parse_error": false,
+                "error": format!("There is no workspace with that name"),
+            }));
+        }
+    }
+    Value::Array(outcomes)
+}
+
+fn find_workspace<'a>(node: &'a mut Value, name: &str) -> Option<&'a mut Value> {
+    if node["type"] == "workspace" && node["name"] == name {
+        return Some(node);
+    }
+    node["nodes"]
+        .as_array_mut()?
+        .iter_mut()
+        .find_map(|child| find_workspace(child, name))
+}
+
+fn workspace_nodes(node: &Value) -> Vec<&Value> {
+    if node["type"] == "workspace" {
+        return vec![node];
+    }
+    node["nodes"]
+        .as_array()
+        .map(|nodes| nodes.iter().flat_map(workspace_nodes).collect())
+        .unwrap_or_default()
+}
+
+/// The reply of `get_workspaces` for the tree
+fn workspaces(tree: &Value) -> Vec<Value> {
+    workspace_nodes(tree)
+        .into_iter()
+        .map(|node| {
+            json!({
+                "id": node["id"],
+                "num": node["num"],
+                "name": node["name"],
+                "visible": true,
+                "focused": node["focused"],
+                "urgent": node["urgent"],
+                "rect": rect(),
+                "output": "FAKE-1",
+            })
+        })
+        .collect()
+}
+
+fn rect() -> Value {
+    json!({ "x": 0, "y": 0, "width": 1920, "height": 1080 })
+}
+
+/// A node with all fields swayipc requires
+pub fn node(id: i64, node_type: &str, name: Option<&str>, nodes: Vec<Value>) -> Value {
+    json!({
+        "id": id,
+        "name": name,
+        "type": node_type,
+        "border": "none",
+        "current_border_width": 0,
+        "layout": "splith",
+        "percent": null,
+        "rect": rect(),
+        "window_rect": rect(),
+        "deco_rect": rect(),
+        "geometry": rect(),
+        "urgent": false,
+        "focused": false,
+        "focus": [],
+        "nodes": nodes,
+        "floating_nodes": [],
+        "sticky": false,
+    })
+}
+
+/// A root node with a single output containing the workspaces
+pub fn tree(workspaces: Vec<Value>) -> Value {
+    node(
+        1,
+        "root",
+        Some("root"),
+        vec![node(2, "output", Some("FAKE-1"), workspaces)],
+    )
+}
+
+pub fn workspace(id: i64, num: i32, name: &str, windows: Vec<Value>) -> Value {
+    let mut workspace = node(id, "workspace", Some(name), windows);
+    workspace["num"] = json!(num);
+    workspace
+}
+
+/// A wayland window
+pub fn window(id: i64, app_id: &str, title: &str) -> Value {
+    let mut window = node(id, "con", Some(title), vec![]);
+    window["app_id"] = json!(app_id);
+    window
+}
@@ -1,104 +1,661 @@
 use std::cmp::Ordering;
-use std::error::Error;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 
 use anyhow::{Result, anyhow};
-use hyprland::dispatch::{Dispatch, DispatchType};
+use futures_util::StreamExt;
+use hyprland::data::*;
+use hyprland::event_listener::{Event, EventStream};
+use hyprland::instance::Instance;
 use hyprland::prelude::*;
-use hyprland::{data::*, event_listener::EventListener};
+use hyprland::shared::Address;
 use log::error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::watch;
 
 use crate::WindowManager;
 use crate::config::SwayNameManagerConfig;
+use crate::markup::WindowState;
+use crate::naming;
+use crate::rename::Rename;
+use crate::wm::{
+    Affected, Backoff, Connected, Debouncer, Output, Tracker, WindowInfo, WorkspaceState, reconnect,
+};
 
 pub struct HyprlandManager {
     pub config: Arc<RwLock<SwayNameManagerConfig>>,
+    instance: Instance,
+    /// The `.socket.sock` of the instance. Batches are not supported by the hyprland crate
+    command_socket: PathBuf,
+    /// Changes when the config was reloaded
+    resync: watch::Receiver<()>,
+    /// Windows which demanded attention and were not focused since. Hyprland does not report
+    /// this in its clients
+    urgent: std::sync::Mutex<HashSet<Address>>,
+    tracker: Tracker<Address>,
+}
+
+/// Returns the clients of the workspace in the order of their symbols
+fn workspace_clients<'a>(workspace: &Workspace, clients: &'a [Client]) -> Vec<&'a Client> {
+    let mut workspace_clients: Vec<&Client> = clients
+        .iter()
+        .filter(|c| c.workspace.id == workspace.id)
+        .collect();
+    // XXX: When in Fullscreen we can't get the information about the actual position of the
+    // window. Since I'd love to stay stateless we'll just push the window to the front
+    workspace_clients.sort_by(|a, b| {
+        if a.fullscreen != FullscreenMode::None {
+            return Ordering::Less;
+        }
+        a.at.cmp(&b.at)
+    });
+    workspace_clients
+}
+
+fn window_info(client: &Client) -> WindowInfo {
+    WindowInfo {
+        // The class of native wayland windows is their app_id
+        app_id: (!client.xwayland).then(|| client.class.clone()),
+        class: Some(client.class.clone()),
+        instance: None,
+        title: Some(client.title.clone()),
+        initial_title: Some(client.initial_title.clone()),
+    }
+}
+
+fn window_state(client: &Client, urgent: &HashSet<Address>) -> WindowState {
+    WindowState {
+        // The focus history starts with the focused client
+        focused: client.focus_history_id == 0,
+        urgent: urgent.contains(&client.address),
+        floating: client.floating,
+        fullscreen: client.fullscreen != FullscreenMode::None,
+    }
+}
+
+/// Returns the name the workspace should have with the given clients
+fn workspace_name(
+    config: &SwayNameManagerConfig,
+    workspace: &Workspace,
+    clients: &[Client],
+    urgent: &HashSet<Address>,
+) -> String {
+    let names: Vec<String> = workspace_clients(workspace, clients)
+        .iter()
+        .filter_map(|client| {
+            config.render_symbol(&window_info(client), window_state(client, urgent))
+        })
+        .collect();
+    naming::workspace_name(config, &workspace.id.to_string(), &workspace.name, &names)
+}
+
+/// The renames of the workspaces whose name differs. `workspaces` are all current workspaces
+fn renames(workspaces: &[Workspace], names: &[(i32, String)]) -> Vec<Rename<i32>> {
+    names
+        .iter()
+        .filter_map(|(id, name)| {
+            let workspace = workspaces.iter().find(|w| w.id == *id)?;
+            (workspace.name != *name).then(|| Rename {
+                id: *id,
+                old: workspace.name.clone(),
+                new: name.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the state of the workspace. `active` is the id of the focused workspace
+fn workspace_state(
+    config: &SwayNameManagerConfig,
+    workspace: &Workspace,
+    clients: &[Client],
+    active: i32,
+    urgent: &HashSet<Address>,
+) -> WorkspaceState {
+    let workspace_clients = workspace_clients(workspace, clients);
+    WorkspaceState {
+        num: workspace.id,
+        name: workspace_name(config, workspace, clients, urgent),
+        focused: workspace.id == active,
+        urgent: workspace_clients
+            .iter()
+            .any(|c| urgent.contains(&c.address)),
+        fullscreen: workspace_clients
+            .iter()
+            .any(|c| c.fullscreen != FullscreenMode::None),
+        windows: workspace_clients.into_iter().map(window_info).collect(),
+    }
 }
 
 impl WindowManager for HyprlandManager {
-    fn get_workspaces(&self) -> Result<Vec<i32>> {
-        Ok(Workspaces::get()?.iter().map(|w| w.id).collect())
+    async fn get_workspaces(&self) -> Result<Vec<(i32, String)>> {
+        Ok(Workspaces::instance_get_async(&self.instance)
+            .await?
+            .iter()
+            .map(|w| (w.id, w.name.clone()))
+            .collect())
     }
-    fn get_workspace_name(&self, id: i32) -> Result<String> {
-        let config = self.config.read().unwrap();
-        let workspaces = Workspaces::get()?.to_vec();
-        let clients = Clients::get()?.to_vec();
+    async fn get_workspace_name(&self, id: i32) -> Result<String> {
+        let workspaces = Workspaces::instance_get_async(&self.instance)
+            .await?
+            .to_vec();
+        let clients = Clients::instance_get_async(&self.instance).await?.to_vec();
         let workspace = workspaces
             .iter()
             .find(|w| w.id == id)
             .ok_or(anyhow!("not found"))?;
-        let mut workspace_clients: Vec<&Client> = clients
+        Ok(workspace_name(
+            &self.config.read().unwrap(),
+            workspace,
+            &clients,
+            &self.urgent.lock().unwrap(),
+        ))
+    }
+
+    fn get_original_name(&self, id: i32) -> Option<String> {
+        self.tracker.original_name(id)
+    }
+
+    async fn update_workspace(&self, id: i32, name: &str) -> Result<()> {
+        self.update_workspaces(&[(id, name.to_string())]).await
+    }
+
+    async fn update_workspaces(&self, names: &[(i32, String)]) -> Result<()> {
+        let workspaces = Workspaces::instance_get_async(&self.instance)
+            .await?
+            .to_vec();
+        self.rename(&workspaces, names).await
+    }
+
+    async fn update_all(&self) -> Result<()> {
+        self.update_affected(Affected::all()).await
+    }
+
+    /// Without the collision handling of sway, since workspaces are renamed by id
+    async fn plan_all(&self) -> Result<Vec<Rename<i32>>> {
+        let workspaces = Workspaces::instance_get_async(&self.instance)
+            .await?
+            .to_vec();
+        let mut names = vec![];
+        for workspace in &workspaces {
+            names.push((workspace.id, self.get_workspace_name(workspace.id).await?));
+        }
+        Ok(renames(&workspaces, &names))
+    }
+}
+
+impl HyprlandManager {
+    /// Sends all renames as one batch. `workspaces` are all current workspaces. Hyprland renames
+    /// by id, so names can't collide and the renames are sent in any order
+    async fn rename(&self, workspaces: &[Workspace], names: &[(i32, String)]) -> Result<()> {
+        let dispatches: Vec<String> = renames(workspaces, names)
             .iter()
-            .filter(|c| c.workspace.id == workspace.id)
+            .map(|r| format!("dispatch renameworkspace {} {}", r.id, r.new))
             .collect();
-        // XXX: When in Fullscreen we can't get the information about the actual position of the
-        // window. Since I'd love to stay stateless we'll just push the window to the front
-        workspace_clients.sort_by(|a, b| {
-            if a.fullscreen != FullscreenMode::None {
-                return Ordering::Less;
-            }
-            a.at.cmp(&b.at)
-        });
-        let names: Vec<String> = workspace_clients
+        // Renaming triggers a rename event, so only rename if any name differs
+        if dispatches.is_empty() {
+            return Ok(());
+        }
+        self.batch(&dispatches).await
+    }
+
+    /// Updates the workspaces affected by events
+    async fn update_affected(&self, affected: Affected<Address>) -> Result<()> {
+        let workspaces = Workspaces::instance_get_async(&self.instance)
+            .await?
+            .to_vec();
+        let clients = Clients::instance_get_async(&self.instance).await?.to_vec();
+        let active = Workspace::instance_get_active_async(&self.instance)
+            .await?
+            .id;
+        let windows: HashMap<Address, i32> = clients
             .iter()
-            .map(|client| {
-                let name = config.get_symbol(&[&client.class, &client.initial_title]);
-                if let Some(color) = &config.fullscreen_color
-                    && client.fullscreen != FullscreenMode::None
-                {
-                    // XXX: Waybar does not support selecting the text with css
-                    format!(r#"<span foreground="{color}">{name}</span>"#)
-                } else {
-                    name
-                }
-            })
+            .map(|c| (c.address.clone(), c.workspace.id))
             .collect();
-        let new_name = names.join("|");
-        Ok(new_name)
+        let mut urgent = self.urgent.lock().unwrap().clone();
+        urgent.retain(|address| windows.contains_key(address));
+        *self.urgent.lock().unwrap() = urgent.clone();
+        let names = {
+            let config = self.config.read().unwrap();
+            let current: Vec<(i32, String, String)> = workspaces
+                .iter()
+                .map(|w| (w.id, w.id.to_string(), w.name.clone()))
+                .collect();
+            self.tracker
+                .update(&config, &affected, &current, windows, |id| {
+                    let workspace = workspaces.iter().find(|w| w.id == id)?;
+                    Some(workspace_state(
+                        &config, workspace, &clients, active, &urgent,
+                    ))
+                })
+        };
+        self.rename(&workspaces, &names).await
     }
 
-    fn update_workspace(&self, id: i32, name: &str) -> Result<()> {
-        Dispatch::call(DispatchType::RenameWorkspace(id, Some(name)))?;
-        Ok(())
+    /// The workspaces an event changes
+    async fn affected_by(&self, event: Event) -> Result<Affected<Address>> {
+        Ok(match event {
+            Event::WindowOpened(event) => Affected::window(event.window_address),
+            // The previous workspace is known from the last update
+            Event::WindowMoved(event) => Affected::window(event.window_address),
+            Event::WindowClosed(address) => Affected::window(address),
+            // Rules can match the title
+            Event::WindowTitleChanged(event) => Affected::window(event.address),
+            Event::FloatStateChanged(event) => Affected::window(event.address),
+            // Only the focused window can change its fullscreen state
+            Event::FullscreenStateChanged(_) => Affected::workspace(
+                Workspace::instance_get_active_async(&self.instance)
+                    .await?
+                    .id,
+            ),
+            // Restores the icons if the user changed the label
+            Event::WorkspaceRenamed(workspace) => Affected::workspace(workspace.id),
+            Event::WorkspaceChanged(workspace) => Affected::workspace(workspace.id),
+            Event::UrgentStateChanged(address) => {
+                self.urgent.lock().unwrap().insert(address.clone());
+                Affected::window(address)
+            }
+            // Focusing a window clears its urgency
+            Event::ActiveWindowChanged(Some(window)) => {
+                self.urgent.lock().unwrap().remove(&window.address);
+                Affected::window(window.address)
+            }
+            _ => Affected::default(),
+        })
     }
-}
 
-impl HyprlandManager {
-    fn update(config: Arc<RwLock<SwayNameManagerConfig>>) -> Result<(), Box<dyn Error>> {
-        HyprlandManager { config }.update_all()?;
+    /// Uses the instance given by HYPRLAND_INSTANCE_SIGNATURE
+    pub fn new(
+        config: Arc<RwLock<SwayNameManagerConfig>>,
+        resync: watch::Receiver<()>,
+        output: Output,
+    ) -> Result<Self> {
+        let runtime_dir =
+            env::var_os("XDG_RUNTIME_DIR").ok_or(anyhow!("XDG_RUNTIME_DIR is not set"))?;
+        let signature = env::var("HYPRLAND_INSTANCE_SIGNATURE")
+            .map_err(|_| anyhow!("HYPRLAND_INSTANCE_SIGNATURE is not set"))?;
+        Self::with_socket_dir(
+            config,
+            resync,
+            output,
+            PathBuf::from(runtime_dir).join("hypr").join(signature),
+        )
+    }
 
-        Ok(())
+    /// Uses the instance with its sockets in `socket_dir`. All workspaces are renamed whenever
+    /// `resync` changes
+    pub fn with_socket_dir(
+        config: Arc<RwLock<SwayNameManagerConfig>>,
+        resync: watch::Receiver<()>,
+        output: Output,
+        socket_dir: PathBuf,
+    ) -> Result<Self> {
+        Ok(Self {
+            config,
+            instance: Instance::from_base_socket_path(socket_dir.clone())?,
+            command_socket: socket_dir.join(".socket.sock"),
+            resync,
+            urgent: Default::default(),
+            tracker: Tracker::new(output),
+        })
     }
-    pub async fn run(&self) -> Result<()> {
-        // Create a event listener
-        let mut event_listener = EventListener::new();
-        let config = self.config.clone();
 
-        event_listener.add_window_opened_handler(move |_| {
-            if let Err(e) = Self::update(config.clone()) {
-                error!("Could not update on window open {e}");
-            };
-        });
-        let config = self.config.clone();
-        event_listener.add_window_moved_handler(move |_| {
-            if let Err(e) = Self::update(config.clone()) {
-                error!("Could not update on window moved {e}");
-            };
-        });
-        let config = self.config.clone();
-        event_listener.add_window_closed_handler(move |_| {
-            if let Err(e) = Self::update(config.clone()) {
-                error!("Could not update on window closed {e}");
+    /// Sends the commands as one request. Hyprland splits batches at `;`, so commands containing
+    /// it are sent on their own
+    async fn batch(&self, commands: &[String]) -> Result<()> {
+        let requests: Vec<String> = if commands.iter().any(|c| c.contains(';')) {
+            commands.to_vec()
+        } else {
+            vec![format!("[[BATCH]]{}", commands.join(";"))]
+        };
+        for request in requests {
+            let mut stream = UnixStream::connect(&self.command_socket).await?;
+            stream.write_all(request.as_bytes()).await?;
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).await?;
+            // Every command of a batch replies on its own
+            if reply.split("\n\n").any(|r| r.trim() != "ok") {
+                return Err(anyhow!("Hyprland rejected {request}: {reply}"));
             }
-        });
-        let config = self.config.clone();
-        event_listener.add_fullscreen_state_changed_handler(move |_| {
-            if let Err(e) = Self::update(config.clone()) {
-                error!("Could not update on fullscreen changed {e}");
+        }
+        Ok(())
+    }
+
+    /// Runs and reconnects with a backoff whenever the event socket is closed. Returns once
+    /// reconnecting failed too often
+    pub async fn run_forever(&self) -> Result<()> {
+        reconnect("HyprlandManager", self).await
+    }
+}
+
+impl Connected for HyprlandManager {
+    /// Runs until the event socket is closed
+    async fn run(&self, backoff: &mut Backoff) -> Result<()> {
+        let events = EventStream::instance_new(self.instance.clone())
+            .then(async |event| self.affected_by(event?).await);
+        let mut events = Debouncer::new(events, self.resync.clone(), self.config.clone());
+        // The event socket is only connected once polled, so a working command socket has to do
+        match self.update_all().await {
+            Ok(()) => backoff.reset(),
+            Err(e) => error!("Could not update workspaces {e}"),
+        }
+        while let Some(affected) = events.next().await {
+            if let Err(e) = self.update_affected(affected?).await {
+                error!("Could not update workspaces {e}");
             }
+        }
+        Err(anyhow!("Hyprland closed the event socket"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::Restore;
+    use crate::wm::fake_hyprland::{FakeHyprland, client, workspace};
+
+    /// Returns the sender triggering a resync once the manager renamed all workspaces and
+    /// connected to the events
+    async fn spawn(fake: &FakeHyprland, config: serde_json::Value) -> watch::Sender<()> {
+        let (resync, receiver) = watch::channel(());
+        let manager = HyprlandManager::with_socket_dir(
+            Arc::new(RwLock::new(serde_json::from_value(config).unwrap())),
+            receiver,
+            Output::default(),
+            fake.socket_dir(),
+        )
+        .unwrap();
+        tokio::spawn(async move { manager.run_forever().await });
+        // The event socket is connected after the first update
+        fake.wait_for_event_connections(1).await;
+        resync
+    }
+
+    fn symbols() -> serde_json::Value {
+        json!({ "app_symbols": { "firefox": "F", "kitty": "K" } })
+    }
+
+    #[tokio::test]
+    async fn renames_on_start() {
+        let fake = FakeHyprland::start(
+            vec![workspace(1, "1"), workspace(2, "2: K"), workspace(3, "3")],
+            vec![
+                client("b", 1, 100, "kitty", "zsh"),
+                client("a", 1, 0, "firefox", "Mozilla Firefox"),
+                client("c", 2, 0, "kitty", "zsh"),
+            ],
+        )
+        .await;
+        spawn(&fake, symbols()).await;
+        assert_eq!(
+            fake.wait_for_dispatches(1).await,
+            ["[[BATCH]]dispatch renameworkspace 1 1: F|K"]
+        );
+        assert_eq!(fake.workspace_names(), ["1: F|K", "2: K", "3"]);
+    }
+
+    #[tokio::test]
+    async fn window_events_rename() {
+        let fake = FakeHyprland::start(vec![workspace(1, "1"), workspace(2, "2")], vec![]).await;
+        spawn(&fake, symbols()).await;
+
+        fake.set_clients(vec![client("a", 1, 0, "kitty", "zsh")]);
+        fake.send_event("openwindow>>a,1,kitty,zsh");
+        assert_eq!(
+            fake.wait_for_dispatches(1).await,
+            ["[[BATCH]]dispatch renameworkspace 1 1: K"]
+        );
+
+        fake.set_clients(vec![client("a", 2, 0, "kitty", "zsh")]);
+        fake.send_event("movewindowv2>>a,2,2");
+        assert_eq!(
+            fake.wait_for_dispatches(2).await[1..],
+            ["[[BATCH]]dispatch renameworkspace 1 1;dispatch renameworkspace 2 2: K"]
+        );
+
+        fake.set_clients(vec![]);
+        fake.send_event("closewindow>>a");
+        assert_eq!(
+            fake.wait_for_dispatches(3).await[2..],
+            ["[[BATCH]]dispatch renameworkspace 2 2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn title_change_updates_only_its_workspace() {
+        let fake = FakeHyprland::start(
+            vec![workspace(1, "1: zsh"), workspace(2, "2: F")],
+            vec![
+                client("a", 1, 0, "kitty", "zsh"),
+                client("b", 2, 0, "firefox", "Mozilla Firefox"),
+            ],
+        )
+        .await;
+        let config = json!({
+            "app_symbols": { "firefox": "F" },
+            "rules": [
+                { "title": "^vim", "symbol": "V" },
+                { "class": "^kitty$", "symbol": "zsh" },
+            ],
         });
-        event_listener.start_listener()?;
+        spawn(&fake, config).await;
 
-        Ok(())
+        // Both workspaces are outdated, but only the one of the window should be renamed
+        fake.set_clients(vec![
+            client("a", 1, 0, "kitty", "vim"),
+            client("b", 2, 0, "unknown", "Mozilla Firefox"),
+        ]);
+        fake.send_event("windowtitlev2>>a,vim");
+        assert_eq!(
+            fake.wait_for_dispatches(1).await,
+            ["[[BATCH]]dispatch renameworkspace 1 1: V"]
+        );
+    }
+
+    #[tokio::test]
+    async fn move_renames_source_and_destination() {
+        let fake = FakeHyprland::start(
+            vec![workspace(1, "1: K"), workspace(2, "2"), workspace(3, "3")],
+            vec![client("a", 1, 0, "kitty", "zsh")],
+        )
+        .await;
+        let resync = spawn(&fake, symbols()).await;
+
+        // Workspace 3 is outdated as well, but no event touched it
+        fake.set_clients(vec![
+            client("a", 2, 0, "kitty", "zsh"),
+            client("b", 3, 0, "firefox", "Mozilla Firefox"),
+        ]);
+        fake.send_event("movewindowv2>>a,2,2");
+        assert_eq!(
+            fake.wait_for_dispatches(1).await,
+            ["[[BATCH]]dispatch renameworkspace 1 1;dispatch renameworkspace 2 2: K"]
+        );
+
+        resync.send_replace(());
+        assert_eq!(
+            fake.wait_for_dispatches(2).await[1..],
+            ["[[BATCH]]dispatch renameworkspace 3 3: F"]
+        );
+    }
+
+    #[tokio::test]
+    async fn fullscreen_color() {
+        let fake = FakeHyprland::start(
+            vec![workspace(1, "1"), workspace(2, "2: F|K")],
+            vec![
+                client("a", 2, 0, "firefox", "Mozilla Firefox"),
+                client("b", 2, 100, "kitty", "zsh"),
+            ],
+        )
+        .await;
+        let mut config = symbols();
+        config["fullscreen_color"] = json!("red");
+        spawn(&fake, config).await;
+
+        let mut fullscreen = client("b", 2, 100, "kitty", "zsh");
+        fullscreen["fullscreen"] = json!(2);
+        fake.set_clients(vec![
+            client("a", 2, 0, "firefox", "Mozilla Firefox"),
+            fullscreen,
+        ]);
+        fake.set_active_workspace(2);
+        fake.send_event("fullscreen>>1");
+        assert_eq!(
+            fake.wait_for_dispatches(1).await,
+            [r#"[[BATCH]]dispatch renameworkspace 2 2: <span foreground="red">K</span>|F"#]
+        );
+    }
+
+    #[tokio::test]
+    async fn floating_style() {
+        let fake = FakeHyprland::start(
+            vec![workspace(1, "1"), workspace(2, "2: F|K")],
+            vec![
+                client("a", 2, 0, "firefox", "Mozilla Firefox"),
+                client("b", 2, 100, "kitty", "zsh"),
+            ],
+        )
+        .await;
+        let mut config = symbols();
+        config["styles"] = json!({ "floating": { "font": "Sans Italic" } });
+        spawn(&fake, config).await;
+
+        let mut floating = client("b", 2, 100, "kitty", "zsh");
+        floating["floating"] = json!(true);
+        fake.set_clients(vec![
+            client("a", 2, 0, "firefox", "Mozilla Firefox"),
+            floating,
+        ]);
+        fake.send_event("changefloatingmode>>b,1");
+        assert_eq!(
+            fake.wait_for_dispatches(1).await,
+            [r#"[[BATCH]]dispatch renameworkspace 2 2: F|<span font="Sans Italic">K</span>"#]
+        );
+    }
+
+    #[tokio::test]
+    async fn urgency_is_cleared_on_focus() {
+        let fake = FakeHyprland::start(
+            vec![workspace(1, "1: K"), workspace(2, "2: F")],
+            vec![
+                client("a", 1, 0, "kitty", "zsh"),
+                client("b", 2, 0, "firefox", "Mozilla Firefox"),
+            ],
+        )
+        .await;
+        let output = Output::default();
+        let mut workspaces = output.workspaces.subscribe();
+        let manager = HyprlandManager::with_socket_dir(
+            Arc::new(RwLock::new(serde_json::from_value(symbols()).unwrap())),
+            watch::channel(()).1,
+            output,
+            fake.socket_dir(),
+        )
+        .unwrap();
+        tokio::spawn(async move { manager.run_forever().await });
+        // The event socket is connected after the first update
+        fake.wait_for_event_connections(1).await;
+
+        fake.send_event("urgent>>b");
+        let states = workspaces.wait_for(|w| w[&2].urgent).await.unwrap().clone();
+        assert!(states[&1].focused && !states[&2].focused);
+
+        fake.set_active_workspace(2);
+        fake.send_event("activewindow>>firefox,Mozilla Firefox");
+        fake.send_event("activewindowv2>>b");
+        let states = workspaces
+            .wait_for(|w| !w[&2].urgent)
+            .await
+            .unwrap()
+            .clone();
+        assert!(!states[&1].focused && states[&2].focused);
+    }
+
+    #[tokio::test]
+    async fn restores_names() {
+        let fake = FakeHyprland::start(
+            // Workspace 2 was named by a previous run
+            vec![workspace(1, "1:mail"), workspace(2, "2: F")],
+            vec![
+                client("a", 1, 0, "firefox", "Mozilla Firefox"),
+                client("b", 2, 0, "kitty", "zsh"),
+            ],
+        )
+        .await;
+        let manager = HyprlandManager::with_socket_dir(
+            Arc::new(RwLock::new(serde_json::from_value(symbols()).unwrap())),
+            watch::channel(()).1,
+            Output::default(),
+            fake.socket_dir(),
+        )
+        .unwrap();
+        manager.update_all().await.unwrap();
+        assert_eq!(fake.workspace_names(), ["1:mail: F", "2: K"]);
+
+        manager.restore(Restore::Original).await.unwrap();
+        assert_eq!(fake.workspace_names(), ["1:mail", "2"]);
+        manager.restore(Restore::Number).await.unwrap();
+        assert_eq!(fake.workspace_names(), ["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn swaps_names_without_temporary_names() {
+        let fake = FakeHyprland::start(
+            vec![workspace(1, "F"), workspace(2, "K")],
+            vec![
+                client("a", 1, 0, "kitty", "zsh"),
+                client("b", 2, 0, "firefox", "Mozilla Firefox"),
+            ],
+        )
+        .await;
+        let mut config = symbols();
+        config["format"] = json!("{icons}");
+        let manager = HyprlandManager::with_socket_dir(
+            Arc::new(RwLock::new(serde_json::from_value(config).unwrap())),
+            watch::channel(()).1,
+            Output::default(),
+            fake.socket_dir(),
+        )
+        .unwrap();
+        let planned: Vec<(i32, String, String)> = manager
+            .plan_all()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.id, r.old, r.new))
+            .collect();
+        assert_eq!(
+            planned,
+            [
+                (1, "F".to_string(), "K".to_string()),
+                (2, "K".to_string(), "F".to_string())
+            ]
+        );
+        manager.update_all().await.unwrap();
+        assert_eq!(
+            fake.dispatches(),
+            ["[[BATCH]]dispatch renameworkspace 1 K;dispatch renameworkspace 2 F"]
+        );
+        assert_eq!(fake.workspace_names(), ["K", "F"]);
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_resyncs() {
+        let fake = FakeHyprland::start(vec![workspace(1, "1")], vec![]).await;
+        spawn(&fake, symbols()).await;
+
+        // Changes while disconnected are picked up after reconnecting
+        fake.close_event_sockets();
+        fake.set_clients(vec![client("a", 1, 0, "kitty", "zsh")]);
+        assert_eq!(
+            fake.wait_for_dispatches(1).await,
+            ["[[BATCH]]dispatch renameworkspace 1 1: K"]
+        );
     }
 }
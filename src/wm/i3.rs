@@ -0,0 +1,31 @@
+use std::{
+    env,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use tokio::sync::watch;
+
+use crate::{SwayNameManager, config::SwayNameManagerConfig, wm::Output};
+
+/// Returns true if I3SOCK belongs to sway. Sway sets it to its own socket for compatibility
+pub fn is_sway() -> bool {
+    env::var("SWAYSOCK").is_ok_and(|sway_socket| env::var("I3SOCK") == Ok(sway_socket))
+}
+
+impl SwayNameManager {
+    /// i3 speaks the same IPC protocol as sway. X11 windows are named from their WM_CLASS
+    /// instance and class
+    pub fn new_i3(
+        config: Arc<RwLock<SwayNameManagerConfig>>,
+        resync: watch::Receiver<()>,
+        output: Output,
+    ) -> Self {
+        Self::with_socket(
+            config,
+            resync,
+            output,
+            env::var_os("I3SOCK").map(PathBuf::from),
+        )
+    }
+}
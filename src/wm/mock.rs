@@ -0,0 +1,511 @@
+use std::{
+    fs::File,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use anyhow::{Result, anyhow};
+use log::info;
+use serde::Deserialize;
+use tokio::sync::watch;
+
+use crate::WindowManager;
+use crate::config::SwayNameManagerConfig;
+use crate::markup::WindowState;
+use crate::naming;
+use crate::wm::{Output, WindowInfo, WorkspaceState};
+
+/// A workspace of a [`Scenario`]
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MockWorkspace {
+    pub num: i32,
+    /// Current name of the workspace. Defaults to the number
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub windows: Vec<WindowInfo>,
+}
+
+/// The state of the mock window manager
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Scenario {
+    pub workspaces: Vec<MockWorkspace>,
+}
+
+impl Scenario {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+}
+
+/// In-memory window manager. Workspaces are only renamed in memory and every rename is recorded
+pub struct MockManager {
+    pub config: Arc<RwLock<SwayNameManagerConfig>>,
+    pub workspaces: Mutex<Vec<MockWorkspace>>,
+    pub renames: Mutex<Vec<(i32, String)>>,
+    /// The workspaces as given by the scenario
+    original: Vec<MockWorkspace>,
+    /// Changes when the config was reloaded
+    resync: watch::Receiver<()>,
+    output: Output,
+}
+
+impl MockManager {
+    /// Renames all workspaces again whenever `resync` changes
+    pub fn new(
+        config: Arc<RwLock<SwayNameManagerConfig>>,
+        scenario: Scenario,
+        resync: watch::Receiver<()>,
+        output: Output,
+    ) -> Self {
+        Self {
+            config,
+            workspaces: Mutex::new(scenario.workspaces.clone()),
+            renames: Mutex::new(vec![]),
+            original: scenario.workspaces,
+            resync,
+            output,
+        }
+    }
+
+    fn find_workspace(&self, id: i32) -> Result<MockWorkspace> {
+        self.workspaces
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|w| w.num == id)
+            .cloned()
+            .ok_or(anyhow!("Workspace {id} not found"))
+    }
+
+    /// Names all workspaces, unless renaming is paused, publishes their states like the other
+    /// backends and logs the result
+    async fn update(&self) -> Result<()> {
+        if self.output.renames() {
+            self.update_all().await?;
+        }
+        let workspaces = self.workspaces.lock().unwrap().clone();
+        let mut states = vec![];
+        for workspace in workspaces {
+            info!(
+                "Workspace {}: {}",
+                workspace.num,
+                workspace.name.as_deref().unwrap_or_default()
+            );
+            let state = WorkspaceState {
+                num: workspace.num,
+                name: self.get_workspace_name(workspace.num).await?,
+                focused: false,
+                urgent: false,
+                fullscreen: false,
+                windows: workspace.windows,
+            };
+            states.push((workspace.num, state));
+        }
+        let existing = states.iter().map(|(num, _)| *num).collect();
+        self.output.publish(&existing, states);
+        Ok(())
+    }
+
+    /// Updates the workspaces and does so again whenever `resync` changes, e.g. after the config
+    /// was reloaded. Runs until shutdown
+    pub async fn run(&self) -> Result<()> {
+        let mut resync = self.resync.clone();
+        loop {
+            resync.borrow_and_update();
+            self.update().await?;
+            if resync.changed().await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl WindowManager for MockManager {
+    async fn get_workspaces(&self) -> Result<Vec<(i32, String)>> {
+        Ok(self
+            .workspaces
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|w| (w.num, w.name.clone().unwrap_or(w.num.to_string())))
+            .collect())
+    }
+
+    async fn get_workspace_name(&self, id: i32) -> Result<String> {
+        let config = self.config.read().unwrap();
+        let workspace = self.find_workspace(id)?;
+        let symbols: Vec<String> = workspace
+            .windows
+            .iter()
+            .filter_map(|window| config.render_symbol(window, WindowState::default()))
+            .collect();
+        let current_name = workspace.name.unwrap_or(id.to_string());
+        Ok(naming::workspace_name(
+            &config,
+            &id.to_string(),
+            &current_name,
+            &symbols,
+        ))
+    }
+
+    /// Like the other backends, icons in the names of the scenario are stripped
+    fn get_original_name(&self, id: i32) -> Option<String> {
+        let workspace = self.original.iter().find(|w| w.num == id)?;
+        let name = workspace.name.clone().unwrap_or(id.to_string());
+        Some(naming::bare_name(
+            &self.config.read().unwrap(),
+            &name,
+            &id.to_string(),
+        ))
+    }
+
+    async fn update_workspace(&self, id: i32, name: &str) -> Result<()> {
+        let mut workspaces = self.workspaces.lock().unwrap();
+        let workspace = workspaces
+            .iter_mut()
+            .find(|w| w.num == id)
+            .ok_or(anyhow!("Workspace {id} not found"))?;
+        if workspace.name.as_deref() != Some(name) {
+            workspace.name = Some(name.to_string());
+            self.renames.lock().unwrap().push((id, name.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::Restore;
+
+    fn mock(config: serde_json::Value, scenario: serde_json::Value) -> MockManager {
+        MockManager::new(
+            Arc::new(RwLock::new(serde_json::from_value(config).unwrap())),
+            serde_json::from_value(scenario).unwrap(),
+            watch::channel(()).1,
+            Output::default(),
+        )
+    }
+
+    async fn names(manager: &MockManager) -> Vec<String> {
+        manager.update_all().await.unwrap();
+        workspace_names(manager)
+    }
+
+    fn workspace_names(manager: &MockManager) -> Vec<String> {
+        manager
+            .workspaces
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|w| w.name.clone().unwrap_or_default())
+            .collect()
+    }
+
+    fn windows(app_ids: &[&str]) -> serde_json::Value {
+        app_ids
+            .iter()
+            .map(|app_id| json!({ "app_id": app_id }))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn default_format() {
+        let manager = mock(
+            json!({ "app_symbols": { "firefox": "F", "Alacritty": "A" } }),
+            json!({ "workspaces": [
+                { "num": 1, "windows": windows(&["firefox", "Alacritty", "unknown"]) },
+                { "num": 2 },
+            ]}),
+        );
+        assert_eq!(names(&manager).await, ["1: F|A|unknown", "2"]);
+    }
+
+    #[tokio::test]
+    async fn app_symbols_before_rules() {
+        let manager = mock(
+            json!({
+                "app_symbols": { "firefox": "exact" },
+                "rules": [
+                    { "match": "^fire", "symbol": "regex" },
+                    { "match": "fire*", "kind": "glob", "symbol": "glob" },
+                    { "match": "*esr", "kind": "glob", "symbol": "esr" },
+                ],
+            }),
+            json!({ "workspaces": [
+                { "num": 1, "windows": windows(&["firefox", "firefox-esr", "x-esr"]) },
+            ]}),
+        );
+        assert_eq!(names(&manager).await, ["1: exact|regex|esr"]);
+    }
+
+    #[tokio::test]
+    async fn rules_on_window_properties() {
+        let manager = mock(
+            json!({
+                "rules": [
+                    { "app_id": "^firefox$", "title": "Slack", "symbol": "S" },
+                    { "app_id": "^firefox$", "symbol": "F" },
+                    { "class": "^Alacritty$", "title": "^nvim", "symbol": "N" },
+                ],
+            }),
+            json!({ "workspaces": [
+                { "num": 1, "windows": [
+                    { "app_id": "firefox", "title": "Slack | Work" },
+                    { "app_id": "firefox", "title": "Mozilla Firefox" },
+                    { "class": "Alacritty", "instance": "alacritty", "title": "nvim main.rs" },
+                    { "class": "Alacritty", "instance": "alacritty", "title": "zsh" },
+                ]},
+            ]}),
+        );
+        assert_eq!(names(&manager).await, ["1: S|F|N|alacritty"]);
+    }
+
+    #[test]
+    fn invalid_rule_is_rejected() {
+        let config: Result<SwayNameManagerConfig, _> =
+            serde_json::from_value(json!({ "rules": [{ "match": "(", "symbol": "x" }] }));
+        assert!(config.is_err());
+        let config: Result<SwayNameManagerConfig, _> =
+            serde_json::from_value(json!({ "rules": [{ "symbol": "x" }] }));
+        assert!(config.is_err());
+    }
+
+    #[tokio::test]
+    async fn custom_format() {
+        let manager = mock(
+            json!({
+                "app_symbols": { "a": "A", "b": "B" },
+                "format": "<{num}> {icons}",
+                "empty_format": "<{num}>",
+                "separator": " ",
+                "prefix": "[",
+                "suffix": "]",
+            }),
+            json!({ "workspaces": [
+                { "num": 1, "windows": windows(&["a", "b"]) },
+                { "num": 2 },
+            ]}),
+        );
+        assert_eq!(names(&manager).await, ["<1> [A] [B]", "<2>"]);
+    }
+
+    #[tokio::test]
+    async fn dedup() {
+        let manager = mock(
+            json!({
+                "app_symbols": { "a": "A", "b": "B" },
+                "dedup": { "threshold": 3 },
+            }),
+            json!({ "workspaces": [
+                { "num": 1, "windows": windows(&["a", "b", "a", "b", "a"]) },
+            ]}),
+        );
+        assert_eq!(names(&manager).await, ["1: A×3|B|B"]);
+
+        let manager = mock(
+            json!({
+                "app_symbols": { "a": "A" },
+                "dedup": { "format": "{symbol}{count_superscript}" },
+            }),
+            json!({ "workspaces": [
+                { "num": 1, "windows": windows(&["a"; 12]) },
+            ]}),
+        );
+        assert_eq!(names(&manager).await, ["1: A¹²"]);
+    }
+
+    #[tokio::test]
+    async fn fallback() {
+        let scenario = json!({ "workspaces": [
+            { "num": 1, "windows": windows(&["org.gnome.Nautilus", "a"]) },
+        ]});
+        let cases = [
+            (json!({ "type": "name" }), "1: org.gnome.Nautilus|A"),
+            (json!({ "type": "symbol", "symbol": "?" }), "1: ?|A"),
+            (json!({ "type": "truncate", "length": 3 }), "1: org|A"),
+            (json!({ "type": "last_segment" }), "1: Nautilus|A"),
+            (json!({ "type": "hide" }), "1: A"),
+        ];
+        for (fallback, expected) in cases {
+            let manager = mock(
+                json!({ "app_symbols": { "a": "A" }, "fallback": fallback }),
+                scenario.clone(),
+            );
+            assert_eq!(names(&manager).await, [expected]);
+        }
+    }
+
+    #[tokio::test]
+    async fn labels() {
+        let manager = mock(
+            json!({ "app_symbols": { "a": "A" } }),
+            json!({ "workspaces": [
+                { "num": 1, "name": "1:mail", "windows": windows(&["a"]) },
+                { "num": 2, "name": "2:web: X", "windows": windows(&["a", "a"]) },
+                { "num": 3, "name": "3: X", "windows": windows(&["a"]) },
+                { "num": 4, "name": "4:chat" },
+            ]}),
+        );
+        assert_eq!(
+            names(&manager).await,
+            ["1:mail: A", "2:web: A|A", "3: A", "4:chat"]
+        );
+    }
+
+    #[tokio::test]
+    async fn labels_with_custom_format() {
+        let manager = mock(
+            json!({ "app_symbols": { "a": "F" }, "format": "{num}{label} {icons}" }),
+            json!({ "workspaces": [
+                { "num": 1, "name": "1:mail", "windows": windows(&["a"]) },
+                { "num": 2, "windows": windows(&["a"]) },
+            ]}),
+        );
+        // The icons must not become part of the label
+        for _ in 0..3 {
+            assert_eq!(names(&manager).await, ["1:mail F", "2 F"]);
+        }
+
+        let manager = mock(
+            json!({ "app_symbols": { "a": "F" }, "label_format": " [{label}]" }),
+            json!({ "workspaces": [
+                { "num": 1, "name": "1:mail", "windows": windows(&["a"]) },
+                { "num": 2, "name": "2 [web]" },
+                { "num": 3, "name": "3: F", "windows": windows(&["a"]) },
+            ]}),
+        );
+        for _ in 0..3 {
+            assert_eq!(names(&manager).await, ["1 [mail]: F", "2 [web]", "3: F"]);
+        }
+
+        // Icons containing the text after the label must not become a label either
+        let manager = mock(
+            json!({}),
+            json!({ "workspaces": [
+                { "num": 2, "name": "2: x: y", "windows": windows(&["x: y"]) },
+                { "num": 3, "name": "3:mail: x: y", "windows": windows(&["x: y"]) },
+            ]}),
+        );
+        for _ in 0..3 {
+            assert_eq!(names(&manager).await, ["2: x: y", "3:mail: x: y"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn markup_is_escaped_and_styled() {
+        let manager = mock(
+            json!({
+                "app_symbols": { "a": "<A>" },
+                "styles": { "app": { "a": { "foreground": "red", "font": "Sans 10" } } },
+            }),
+            json!({ "workspaces": [
+                { "num": 1, "name": "1:R&D", "windows": windows(&["a", "b&c"]) },
+                { "num": 2, "name": "2:R&amp;D", "windows": windows(&["b&c"]) },
+            ]}),
+        );
+        assert_eq!(
+            names(&manager).await,
+            [
+                r#"1:R&amp;D: <span foreground="red" font="Sans 10">&lt;A&gt;</span>|b&amp;c"#,
+                "2:R&amp;D: b&amp;c"
+            ]
+        );
+
+        // Without markup nothing is escaped
+        let manager = mock(
+            json!({ "app_symbols": { "a": "<A>" } }),
+            json!({ "workspaces": [{ "num": 1, "name": "1:R&D", "windows": windows(&["a"]) }]}),
+        );
+        assert_eq!(names(&manager).await, ["1:R&D: <A>"]);
+    }
+
+    #[tokio::test]
+    async fn restore() {
+        let manager = mock(
+            json!({ "app_symbols": { "a": "A" } }),
+            json!({ "workspaces": [
+                { "num": 1, "name": "1:mail", "windows": windows(&["a"]) },
+                { "num": 2, "windows": windows(&["a"]) },
+                { "num": 3, "name": "3: B", "windows": windows(&["a"]) },
+            ]}),
+        );
+        assert_eq!(names(&manager).await, ["1:mail: A", "2: A", "3: A"]);
+        manager.restore(Restore::Original).await.unwrap();
+        assert_eq!(workspace_names(&manager), ["1:mail", "2", "3"]);
+        manager.restore(Restore::Number).await.unwrap();
+        assert_eq!(workspace_names(&manager), ["1", "2", "3"]);
+    }
+
+    #[tokio::test]
+    async fn plan_all_does_not_rename() {
+        let manager = mock(
+            json!({ "app_symbols": { "a": "A" } }),
+            json!({ "workspaces": [
+                { "num": 1, "name": "1: A", "windows": windows(&["a"]) },
+                { "num": 2, "windows": windows(&["a"]) },
+            ]}),
+        );
+        let planned: Vec<(i32, String, String)> = manager
+            .plan_all()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.id, r.old, r.new))
+            .collect();
+        assert_eq!(planned, [(2, "2".to_string(), "2: A".to_string())]);
+        assert!(manager.renames.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_changed_workspaces_are_renamed() {
+        let manager = mock(
+            json!({ "app_symbols": { "a": "A" } }),
+            json!({ "workspaces": [
+                { "num": 1, "name": "1: A", "windows": windows(&["a"]) },
+                { "num": 2, "name": "2", "windows": windows(&["a"]) },
+            ]}),
+        );
+        manager.update_all().await.unwrap();
+        manager.update_all().await.unwrap();
+        assert_eq!(*manager.renames.lock().unwrap(), [(2, "2: A".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn run_publishes_states() {
+        let config = Arc::new(RwLock::new(
+            serde_json::from_value(json!({ "app_symbols": { "a": "A" } })).unwrap(),
+        ));
+        let (resync, receiver) = watch::channel(());
+        let output = Output::default();
+        output.paused.send_replace(true);
+        let mut workspaces = output.workspaces.subscribe();
+        let manager = MockManager::new(
+            config.clone(),
+            serde_json::from_value(json!({ "workspaces": [
+                { "num": 1, "windows": windows(&["a"]) },
+                { "num": 2 },
+            ]}))
+            .unwrap(),
+            receiver,
+            output,
+        );
+        tokio::spawn(async move { manager.run().await });
+        let states = workspaces.wait_for(|w| w.len() == 2).await.unwrap().clone();
+        // Published even while paused
+        assert_eq!(states[&1].name, "1: A");
+        assert_eq!(states[&1].windows[0].app_id.as_deref(), Some("a"));
+        assert_eq!(states[&2].name, "2");
+
+        config
+            .write()
+            .unwrap()
+            .app_symbols
+            .insert("a".to_string(), "B".to_string());
+        resync.send_replace(());
+        workspaces.wait_for(|w| w[&1].name == "1: B").await.unwrap();
+    }
+}
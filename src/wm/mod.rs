@@ -1,4 +1,124 @@
+#[cfg(any(
+    feature = "sway",
+    feature = "i3",
+    feature = "hyprland",
+    feature = "niri"
+))]
+mod backend;
+#[cfg(all(test, feature = "hyprland"))]
+pub mod fake_hyprland;
+#[cfg(all(test, feature = "niri"))]
+pub mod fake_niri;
+#[cfg(all(test, any(feature = "sway", feature = "i3")))]
+pub mod fake_sway;
 #[cfg(feature = "hyprland")]
 pub mod hyprland;
-#[cfg(feature = "sway")]
+#[cfg(feature = "i3")]
+pub mod i3;
+pub mod mock;
+#[cfg(feature = "niri")]
+pub mod niri;
+#[cfg(any(feature = "sway", feature = "i3"))]
 pub mod sway;
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+#[cfg(any(
+    feature = "sway",
+    feature = "i3",
+    feature = "hyprland",
+    feature = "niri"
+))]
+pub use backend::*;
+
+/// Properties of a window used to select its symbol. Fields a backend does not know about are
+/// `None`
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct WindowInfo {
+    /// The wayland app_id
+    pub app_id: Option<String>,
+    /// The X11 WM_CLASS class. On hyprland this is the class of every window
+    pub class: Option<String>,
+    /// The X11 WM_CLASS instance
+    pub instance: Option<String>,
+    /// The current window title
+    pub title: Option<String>,
+    /// The title the window had when it was opened
+    pub initial_title: Option<String>,
+}
+
+impl WindowInfo {
+    /// All names identifying the application of the window, ordered by priority
+    pub fn names(&self) -> Vec<&str> {
+        [
+            &self.app_id,
+            &self.instance,
+            &self.class,
+            &self.initial_title,
+        ]
+        .into_iter()
+        .filter_map(|name| name.as_deref())
+        .collect()
+    }
+}
+
+/// State of a workspace after an update. Outputs other than renaming, like Waybar, are built from
+/// it
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct WorkspaceState {
+    /// The number shown to the user. On niri this is the index on its output
+    pub num: i32,
+    /// The name according to the config
+    pub name: String,
+    pub focused: bool,
+    pub urgent: bool,
+    /// Any window of the workspace is fullscreen
+    pub fullscreen: bool,
+    /// The windows in the order of their symbols
+    pub windows: Vec<WindowInfo>,
+}
+
+/// Where the managers send the names they computed
+#[derive(Debug, Clone)]
+pub struct Output {
+    /// The state of every workspace by id
+    pub workspaces: watch::Sender<BTreeMap<i32, WorkspaceState>>,
+    /// Set while renaming is paused on request
+    pub paused: watch::Sender<bool>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            workspaces: watch::Sender::new(BTreeMap::new()),
+            paused: watch::Sender::new(false),
+        }
+    }
+}
+
+impl Output {
+    /// Whether the workspaces are renamed right now
+    pub fn renames(&self) -> bool {
+        !*self.paused.borrow()
+    }
+
+    /// Stores the states of the updated workspaces and drops the ones not in `existing`
+    pub fn publish(&self, existing: &HashSet<i32>, updated: Vec<(i32, WorkspaceState)>) {
+        self.workspaces.send_if_modified(|workspaces| {
+            let count = workspaces.len();
+            workspaces.retain(|id, _| existing.contains(id));
+            let mut modified = workspaces.len() != count;
+            for (id, state) in updated {
+                if workspaces.get(&id) != Some(&state) {
+                    workspaces.insert(id, state);
+                    modified = true;
+                }
+            }
+            modified
+        });
+    }
+}
@@ -0,0 +1,630 @@
+use std::{
+    collections::HashSet,
+    env,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Result, anyhow};
+use futures_util::stream;
+use log::error;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+    sync::watch,
+};
+
+use crate::WindowManager;
+use crate::config::SwayNameManagerConfig;
+use crate::markup::WindowState;
+use crate::naming;
+use crate::rename::{self, Rename};
+use crate::wm::{
+    Affected, Backoff, Connected, Debouncer, Output, Tracker, WindowInfo, WorkspaceState, reconnect,
+};
+
+pub struct NiriManager {
+    pub config: Arc<RwLock<SwayNameManagerConfig>>,
+    /// Path of the IPC socket. `None` if NIRI_SOCKET is not set
+    socket_path: Option<PathBuf>,
+    /// Changes when the config was reloaded
+    resync: watch::Receiver<()>,
+    tracker: Tracker<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Workspace {
+    id: u64,
+    /// Index of the workspace on its output
+    idx: u8,
+    name: Option<String>,
+    output: Option<String>,
+    /// Focused on the focused output. Not reported by old niri versions
+    #[serde(default)]
+    is_focused: bool,
+    #[serde(default)]
+    is_urgent: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Window {
+    id: u64,
+    title: Option<String>,
+    app_id: Option<String>,
+    workspace_id: Option<u64>,
+    #[serde(default)]
+    is_focused: bool,
+    #[serde(default)]
+    is_urgent: bool,
+    #[serde(default)]
+    layout: WindowLayout,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+struct WindowLayout {
+    /// Column and position in the column. Not set for floating windows
+    pos_in_scrolling_layout: Option<(usize, usize)>,
+}
+
+#[derive(Deserialize, Debug)]
+enum Response {
+    Handled,
+    Workspaces(Vec<Workspace>),
+    Windows(Vec<Window>),
+}
+
+pub fn socket_path() -> Result<String> {
+    env::var("NIRI_SOCKET").map_err(|_| anyhow!("NIRI_SOCKET is not set"))
+}
+
+impl Workspace {
+    fn id(&self) -> Option<i32> {
+        i32::try_from(self.id).ok()
+    }
+
+    /// `{num}` of the workspace. niri numbers the workspaces of every output from 1, so with
+    /// several outputs the output is added to keep the names unique, e.g. `1@DP-2`
+    fn num(&self, several_outputs: bool) -> String {
+        match &self.output {
+            Some(output) if several_outputs => format!("{}@{output}", self.idx),
+            _ => self.idx.to_string(),
+        }
+    }
+}
+
+fn several_outputs(workspaces: &[Workspace]) -> bool {
+    let outputs: HashSet<&Option<String>> = workspaces.iter().map(|w| &w.output).collect();
+    outputs.len() > 1
+}
+
+/// Whether the workspace has a name which was neither set by us nor typed as `num:label`, e.g. one
+/// from the niri config. Unsetting or overwriting it would make niri forget the workspace
+fn is_named_by_config(config: &SwayNameManagerConfig, workspace: &Workspace) -> bool {
+    workspace.name.as_deref().is_some_and(|name| {
+        // Names set before an output was added or removed use the other number
+        ![workspace.num(true), workspace.num(false)]
+            .iter()
+            .any(|num| naming::is_managed(config, name, num))
+    })
+}
+
+/// Returns the windows of the workspace in the order of their symbols
+fn workspace_windows<'a>(workspace: &Workspace, windows: &'a [Window]) -> Vec<&'a Window> {
+    let mut windows: Vec<&Window> = windows
+        .iter()
+        .filter(|w| w.workspace_id == Some(workspace.id))
+        .collect();
+    // Tiled windows ordered by their column, followed by the floating ones
+    windows.sort_by_key(|w| {
+        (
+            w.layout.pos_in_scrolling_layout.is_none(),
+            w.layout.pos_in_scrolling_layout,
+            w.id,
+        )
+    });
+    windows
+}
+
+fn window_info(window: &Window) -> WindowInfo {
+    WindowInfo {
+        app_id: window.app_id.clone(),
+        class: None,
+        instance: None,
+        title: window.title.clone(),
+        initial_title: None,
+    }
+}
+
+/// Returns the name the workspace should have with the given windows. Workspaces named by the
+/// niri config keep their name
+fn workspace_name(
+    config: &SwayNameManagerConfig,
+    workspace: &Workspace,
+    windows: &[Window],
+    several_outputs: bool,
+) -> String {
+    let current_name = workspace.name.as_deref().unwrap_or_default();
+    if is_named_by_config(config, workspace) {
+        return current_name.to_string();
+    }
+    let names: Vec<String> = workspace_windows(workspace, windows)
+        .iter()
+        .filter_map(|window| {
+            let state = WindowState {
+                focused: window.is_focused,
+                urgent: window.is_urgent,
+                floating: window.layout.pos_in_scrolling_layout.is_none(),
+                // niri does not report fullscreen windows
+                fullscreen: false,
+            };
+            config.render_symbol(&window_info(window), state)
+        })
+        .collect();
+    naming::workspace_name(
+        config,
+        &workspace.num(several_outputs),
+        current_name,
+        &names,
+    )
+}
+
+fn workspace_state(
+    config: &SwayNameManagerConfig,
+    workspace: &Workspace,
+    windows: &[Window],
+    several_outputs: bool,
+) -> WorkspaceState {
+    let workspace_windows = workspace_windows(workspace, windows);
+    WorkspaceState {
+        num: workspace.idx.into(),
+        name: workspace_name(config, workspace, windows, several_outputs),
+        focused: workspace.is_focused || workspace_windows.iter().any(|w| w.is_focused),
+        urgent: workspace.is_urgent || workspace_windows.iter().any(|w| w.is_urgent),
+        // niri does not report fullscreen windows
+        fullscreen: false,
+        windows: workspace_windows.into_iter().map(window_info).collect(),
+    }
+}
+
+/// Orders the renames of the workspaces. `workspaces` are all current workspaces. Workspaces
+/// named by the niri config are skipped. A workspace renamed to its number gets its name unset
+/// instead, since niri keeps named workspaces even when empty. Unsetting needs no free name and
+/// frees the old one, so these come first
+fn plan(
+    config: &SwayNameManagerConfig,
+    workspaces: &[Workspace],
+    names: &[(i32, String)],
+) -> Vec<Rename<i32>> {
+    let several_outputs = several_outputs(workspaces);
+    let mut unsets = vec![];
+    let mut renames = vec![];
+    for (id, name) in names {
+        let Some(workspace) = workspaces.iter().find(|w| w.id() == Some(*id)) else {
+            continue;
+        };
+        if is_named_by_config(config, workspace) {
+            continue;
+        }
+        // Unnamed workspaces are shown with their index
+        let idx = workspace.idx.to_string();
+        let old = workspace.name.clone().unwrap_or(idx.clone());
+        if *name == idx || *name == workspace.num(several_outputs) {
+            if workspace.name.is_some() {
+                unsets.push(Rename {
+                    id: *id,
+                    old,
+                    new: idx,
+                });
+            }
+        } else {
+            renames.push(Rename {
+                id: *id,
+                old,
+                new: name.clone(),
+            });
+        }
+    }
+    let unset: HashSet<i32> = unsets.iter().map(|r| r.id).collect();
+    let current_names: Vec<String> = workspaces
+        .iter()
+        .filter(|w| w.id().is_none_or(|id| !unset.contains(&id)))
+        .filter_map(|w| w.name.clone())
+        .collect();
+    unsets.extend(rename::plan(renames, &current_names));
+    unsets
+}
+
+/// The workspaces an event changes
+fn affected_by(event_type: &str, event: &Value) -> Affected<u64> {
+    let window = |id: &Value| id.as_u64().map(Affected::window).unwrap_or_default();
+    let workspace = |id: &Value| {
+        id.as_u64()
+            .and_then(|id| i32::try_from(id).ok())
+            .map(Affected::workspace)
+            .unwrap_or_default()
+    };
+    match event_type {
+        // These contain the full state and are sent when the stream starts
+        "WorkspacesChanged" | "WindowsChanged" => Affected::all(),
+        "WindowOpenedOrChanged" => window(&event["window"]["id"]),
+        "WindowClosed" | "WindowFocusChanged" | "WindowUrgencyChanged" => window(&event["id"]),
+        "WorkspaceActivated" | "WorkspaceUrgencyChanged" => workspace(&event["id"]),
+        "WindowLayoutsChanged" => Affected {
+            windows: event["changes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|change| change[0].as_u64())
+                .collect(),
+            ..Default::default()
+        },
+        _ => Affected::default(),
+    }
+}
+
+fn workspace_ref(id: i32) -> Value {
+    json!({ "Id": id })
+}
+
+impl WindowManager for NiriManager {
+    /// Unnamed workspaces are shown with their index
+    async fn get_workspaces(&self) -> Result<Vec<(i32, String)>> {
+        self.fetch_workspaces()
+            .await?
+            .iter()
+            .map(|w| {
+                let name = w.name.clone().unwrap_or(w.idx.to_string());
+                Ok((i32::try_from(w.id)?, name))
+            })
+            .collect()
+    }
+
+    async fn get_workspace_name(&self, id: i32) -> Result<String> {
+        let workspaces = self.fetch_workspaces().await?;
+        let workspace = find_workspace(&workspaces, id)?;
+        let windows = self.fetch_windows().await?;
+        Ok(workspace_name(
+            &self.config.read().unwrap(),
+            workspace,
+            &windows,
+            several_outputs(&workspaces),
+        ))
+    }
+
+    /// Unnamed workspaces are shown with their index
+    async fn get_bare_name(&self, id: i32) -> Result<String> {
+        let workspaces = self.fetch_workspaces().await?;
+        Ok(find_workspace(&workspaces, id)?.idx.to_string())
+    }
+
+    fn get_original_name(&self, id: i32) -> Option<String> {
+        self.tracker.original_name(id)
+    }
+
+    async fn update_workspace(&self, id: i32, name: &str) -> Result<()> {
+        self.update_workspaces(&[(id, name.to_string())]).await
+    }
+
+    async fn update_workspaces(&self, names: &[(i32, String)]) -> Result<()> {
+        self.rename(&self.fetch_workspaces().await?, names).await
+    }
+
+    async fn update_all(&self) -> Result<()> {
+        self.update_affected(Affected::all()).await
+    }
+
+    /// Unsets names instead of renaming to the number and skips workspaces named by the config
+    async fn plan_all(&self) -> Result<Vec<Rename<i32>>> {
+        let workspaces = self.fetch_workspaces().await?;
+        let windows = self.fetch_windows().await?;
+        let config = self.config.read().unwrap();
+        let several_outputs = several_outputs(&workspaces);
+        let names: Vec<(i32, String)> = workspaces
+            .iter()
+            .filter_map(|w| {
+                Some((
+                    w.id()?,
+                    workspace_name(&config, w, &windows, several_outputs),
+                ))
+            })
+            .collect();
+        Ok(plan(&config, &workspaces, &names))
+    }
+}
+
+fn find_workspace(workspaces: &[Workspace], id: i32) -> Result<&Workspace> {
+    workspaces
+        .iter()
+        .find(|w| w.id() == Some(id))
+        .ok_or(anyhow!("not found"))
+}
+
+impl NiriManager {
+    /// Uses the socket given by NIRI_SOCKET
+    pub fn new(
+        config: Arc<RwLock<SwayNameManagerConfig>>,
+        resync: watch::Receiver<()>,
+        output: Output,
+    ) -> Self {
+        Self::with_socket(
+            config,
+            resync,
+            output,
+            socket_path().ok().map(PathBuf::from),
+        )
+    }
+
+    /// Renames all workspaces whenever `resync` changes
+    pub fn with_socket(
+        config: Arc<RwLock<SwayNameManagerConfig>>,
+        resync: watch::Receiver<()>,
+        output: Output,
+        socket_path: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            config,
+            socket_path,
+            resync,
+            tracker: Tracker::new(output),
+        }
+    }
+
+    async fn connect(&self) -> Result<UnixStream> {
+        let socket_path = self
+            .socket_path
+            .as_ref()
+            .ok_or(anyhow!("NIRI_SOCKET is not set"))?;
+        Ok(UnixStream::connect(socket_path).await?)
+    }
+
+    /// Sends a single request to niri and returns its response
+    async fn request(&self, request: &Value) -> Result<Response> {
+        let mut stream = self.connect().await?;
+        stream.write_all(format!("{request}\n").as_bytes()).await?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await?;
+        let reply: Result<Response, String> = serde_json::from_str(&line)?;
+        reply.map_err(|e| anyhow!("niri returned an error: {e}"))
+    }
+
+    async fn fetch_workspaces(&self) -> Result<Vec<Workspace>> {
+        match self.request(&json!("Workspaces")).await? {
+            Response::Workspaces(workspaces) => Ok(workspaces),
+            response => Err(anyhow!("Unexpected response {response:?}")),
+        }
+    }
+
+    async fn fetch_windows(&self) -> Result<Vec<Window>> {
+        match self.request(&json!("Windows")).await? {
+            Response::Windows(windows) => Ok(windows),
+            response => Err(anyhow!("Unexpected response {response:?}")),
+        }
+    }
+
+    /// niri has no batches, so the planned renames are sent one by one. `workspaces` are all
+    /// current workspaces
+    async fn rename(&self, workspaces: &[Workspace], names: &[(i32, String)]) -> Result<()> {
+        let steps = plan(&self.config.read().unwrap(), workspaces, names);
+        for Rename { id, new, .. } in steps {
+            let unset = find_workspace(workspaces, id)?.idx.to_string() == new;
+            let action = if unset {
+                json!({ "UnsetWorkspaceName": { "reference": workspace_ref(id) } })
+            } else {
+                json!({ "SetWorkspaceName": { "name": new, "workspace": workspace_ref(id) } })
+            };
+            match self.request(&json!({ "Action": action })).await? {
+                Response::Handled => {}
+                response => return Err(anyhow!("Unexpected response {response:?}")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates the workspaces affected by events
+    async fn update_affected(&self, affected: Affected<u64>) -> Result<()> {
+        let workspaces = self.fetch_workspaces().await?;
+        let windows = self.fetch_windows().await?;
+        let several_outputs = several_outputs(&workspaces);
+        let current: Vec<(i32, String, String)> = workspaces
+            .iter()
+            .filter_map(|w| {
+                let name = w.name.clone().unwrap_or(w.idx.to_string());
+                Some((w.id()?, w.num(several_outputs), name))
+            })
+            .collect();
+        let window_workspaces = windows
+            .iter()
+            .filter_map(|w| Some((w.id, i32::try_from(w.workspace_id?).ok()?)))
+            .collect();
+        let names = {
+            let config = self.config.read().unwrap();
+            self.tracker
+                .update(&config, &affected, &current, window_workspaces, |id| {
+                    let workspace = find_workspace(&workspaces, id).ok()?;
+                    Some(workspace_state(
+                        &config,
+                        workspace,
+                        &windows,
+                        several_outputs,
+                    ))
+                })
+        };
+        self.rename(&workspaces, &names).await
+    }
+
+    /// Runs and reconnects with a backoff whenever the event stream is closed. Returns once
+    /// reconnecting failed too often
+    pub async fn run_forever(&self) -> Result<()> {
+        if self.socket_path.is_none() {
+            return Err(anyhow!("NIRI_SOCKET is not set"));
+        }
+        reconnect("NiriManager", self).await
+    }
+}
+
+impl Connected for NiriManager {
+    /// Runs until niri closes the event stream. `backoff` is reset once connected
+    async fn run(&self, backoff: &mut Backoff) -> Result<()> {
+        let mut stream = self.connect().await?;
+        stream.write_all(b"\"EventStream\"\n").await?;
+        backoff.reset();
+        let lines = BufReader::new(stream).lines();
+        let events = stream::try_unfold(lines, async |mut lines| -> Result<_> {
+            let Some(line) = lines.next_line().await? else {
+                return Ok(None);
+            };
+            let event: Value = serde_json::from_str(&line)?;
+            let affected = match event.as_object().and_then(|o| o.iter().next()) {
+                Some((event_type, event)) => affected_by(event_type, event),
+                None => Affected::default(),
+            };
+            Ok(Some((affected, lines)))
+        });
+        let mut events = Debouncer::new(events, self.resync.clone(), self.config.clone());
+        if let Err(e) = self.update_all().await {
+            error!("Could not update workspaces {e}");
+        }
+        while let Some(affected) = events.next().await {
+            if let Err(e) = self.update_affected(affected?).await {
+                error!("Could not update workspaces {e}");
+            }
+        }
+        Err(anyhow!("niri closed the event stream"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::Restore;
+    use crate::wm::fake_niri::{FakeNiri, window, workspace};
+
+    fn manager(fake: &FakeNiri, config: Value) -> NiriManager {
+        NiriManager::with_socket(
+            Arc::new(RwLock::new(serde_json::from_value(config).unwrap())),
+            watch::channel(()).1,
+            Output::default(),
+            Some(fake.socket_path()),
+        )
+    }
+
+    fn symbols() -> Value {
+        json!({ "app_symbols": { "firefox": "F", "kitty": "K" } })
+    }
+
+    #[tokio::test]
+    async fn renames_on_start_and_on_events() {
+        let fake = FakeNiri::start(
+            vec![workspace(1, 1, None, "DP-1"), workspace(2, 2, None, "DP-1")],
+            vec![
+                window(1, 1, 2, "kitty", "zsh"),
+                window(2, 1, 1, "firefox", "Mozilla Firefox"),
+            ],
+        )
+        .await;
+        let manager = manager(&fake, symbols());
+        tokio::spawn(async move { manager.run_forever().await });
+        assert_eq!(
+            fake.wait_for_actions(1).await,
+            ["SetWorkspaceName 1 1: F|K"]
+        );
+
+        fake.wait_for_event_streams(1).await;
+        fake.set_windows(vec![
+            window(1, 2, 1, "kitty", "zsh"),
+            window(2, 1, 1, "firefox", "Mozilla Firefox"),
+        ]);
+        assert_eq!(
+            fake.wait_for_actions(3).await[1..],
+            ["SetWorkspaceName 1 1: F", "SetWorkspaceName 2 2: K"]
+        );
+        assert_eq!(
+            fake.workspace_names(),
+            [Some("1: F".to_string()), Some("2: K".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn unsets_names_of_empty_workspaces() {
+        let fake = FakeNiri::start(
+            vec![
+                workspace(1, 1, Some("1: K"), "DP-1"),
+                workspace(2, 2, None, "DP-1"),
+            ],
+            vec![],
+        )
+        .await;
+        let manager = manager(&fake, symbols());
+        manager.update_all().await.unwrap();
+        // Unnamed workspaces are left alone
+        assert_eq!(fake.actions(), ["UnsetWorkspaceName 1"]);
+        assert_eq!(fake.workspace_names(), [None, None]);
+    }
+
+    #[tokio::test]
+    async fn names_are_unique_across_outputs() {
+        let fake = FakeNiri::start(
+            vec![
+                // Named while DP-1 was the only output
+                workspace(1, 1, Some("1: K"), "DP-1"),
+                workspace(2, 1, None, "DP-2"),
+                workspace(3, 2, Some("2: F"), "DP-1"),
+            ],
+            vec![
+                window(1, 1, 1, "kitty", "zsh"),
+                window(2, 2, 1, "kitty", "zsh"),
+            ],
+        )
+        .await;
+        let manager = manager(&fake, symbols());
+        manager.update_all().await.unwrap();
+        assert_eq!(
+            fake.workspace_names(),
+            [
+                Some("1@DP-1: K".to_string()),
+                Some("1@DP-2: K".to_string()),
+                None
+            ]
+        );
+        assert_eq!(fake.actions()[0], "UnsetWorkspaceName 3");
+    }
+
+    #[tokio::test]
+    async fn keeps_names_from_the_niri_config() {
+        let fake = FakeNiri::start(
+            vec![
+                workspace(1, 1, Some("chat"), "DP-1"),
+                workspace(2, 2, Some("2:mail"), "DP-1"),
+                workspace(3, 3, None, "DP-1"),
+            ],
+            vec![
+                window(1, 1, 1, "kitty", "zsh"),
+                window(2, 2, 1, "firefox", "Mozilla Firefox"),
+            ],
+        )
+        .await;
+        let manager = manager(&fake, symbols());
+        manager.update_all().await.unwrap();
+        assert_eq!(fake.actions(), ["SetWorkspaceName 2 2:mail: F"]);
+        assert_eq!(
+            manager.tracker.output.workspaces.borrow()[&1].name,
+            "chat",
+            "shown with its name"
+        );
+
+        manager.restore(Restore::Original).await.unwrap();
+        assert_eq!(
+            fake.workspace_names(),
+            [Some("chat".to_string()), Some("2:mail".to_string()), None]
+        );
+        manager.restore(Restore::Number).await.unwrap();
+        assert_eq!(
+            fake.workspace_names(),
+            [Some("chat".to_string()), None, None]
+        );
+    }
+}
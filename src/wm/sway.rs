@@ -1,47 +1,99 @@
 use std::{
-    error::Error,
+    collections::HashMap,
+    os::unix::net::UnixStream,
+    path::PathBuf,
     sync::{Arc, RwLock},
 };
 
 use anyhow::anyhow;
+use async_io::Async;
 use futures_util::StreamExt;
 use log::error;
-use swayipc_async::{Connection, Event, EventType, Fallible, Node, NodeType, WindowChange};
+use swayipc_async::{
+    Connection, Error, Event, EventType, Fallible, Floating, Node, NodeType, WindowChange,
+    WorkspaceChange,
+};
+use tokio::sync::{Mutex, watch};
 
-use crate::{SwayNameManager, WindowManager, config::SwayNameManagerConfig};
+use crate::{
+    SwayNameManager, WindowManager,
+    config::SwayNameManagerConfig,
+    markup::WindowState,
+    naming,
+    rename::{self, Rename},
+    wm::{
+        Affected, Backoff, Connected, Debouncer, Output, Tracker, WindowInfo, WorkspaceState,
+        reconnect,
+    },
+};
 
 trait Autorename {
-    #[allow(dead_code)]
-    fn contains(&self, node: &Node) -> bool;
-    #[allow(dead_code)]
-    fn get_workspace<'a>(&'a self, node: &'a Node) -> Result<&'a Node, Box<dyn Error>>;
-    #[allow(dead_code)]
     fn get_workspace_nodes(&self) -> Vec<&Node>;
-    fn get_window_names(&self) -> Vec<String>;
-    async fn update_workspace_names(&self, name_config: &SwayNameManagerConfig);
+    fn get_views(&self) -> Vec<&Node>;
+    fn get_windows(&self, initial_titles: &InitialTitles) -> Vec<WindowInfo>;
+    fn get_window_workspaces(&self) -> HashMap<i64, i32>;
+    fn get_new_name(
+        &self,
+        name_config: &SwayNameManagerConfig,
+        initial_titles: &InitialTitles,
+    ) -> Option<String>;
+    fn contains_focus(&self) -> bool;
+    fn get_state(
+        &self,
+        name_config: &SwayNameManagerConfig,
+        initial_titles: &InitialTitles,
+    ) -> Option<WorkspaceState>;
+    fn get_renames(
+        &self,
+        name_config: &SwayNameManagerConfig,
+        initial_titles: &InitialTitles,
+    ) -> Vec<Rename<i64>>;
+    async fn rename_workspaces(
+        &self,
+        renames: Vec<Rename<i64>>,
+        connection: &mut Connection,
+    ) -> Fallible<()>;
 }
 
-impl Autorename for Node {
-    fn contains(&self, node: &Node) -> bool {
-        self.id == node.id || self.nodes.iter().any(|child| child.contains(node))
-    }
+/// The first title of every window by container id. Neither sway nor i3 keep track of it
+pub type InitialTitles = HashMap<i64, String>;
 
-    fn get_workspace<'a>(&'a self, node: &'a Node) -> Result<&'a Node, Box<dyn Error>> {
-        let workspaces = self.get_workspace_nodes();
+fn title(view: &Node) -> Option<String> {
+    view.name.clone().or_else(|| {
+        view.window_properties
+            .as_ref()
+            .and_then(|p| p.title.clone())
+    })
+}
 
-        let nodes: Vec<&Node> = workspaces
-            .iter()
-            .filter(|workspace| workspace.contains(node))
-            .copied()
-            .collect();
+fn window_info(view: &Node, initial_titles: &InitialTitles) -> WindowInfo {
+    let properties = view.window_properties.as_ref();
+    WindowInfo {
+        app_id: view.app_id.clone(),
+        class: properties.and_then(|p| p.class.clone()),
+        instance: properties.and_then(|p| p.instance.clone()),
+        title: title(view),
+        initial_title: initial_titles.get(&view.id).cloned(),
+    }
+}
 
-        if nodes.len() == 1 {
-            Ok(nodes.first().unwrap())
-        } else {
-            Err("Window is on multiple workspaces!".into())
-        }
+fn window_state(view: &Node) -> WindowState {
+    WindowState {
+        focused: view.focused,
+        urgent: view.urgent,
+        // Sway makes the view itself the floating_con, i3 wraps it in one and sets `floating`
+        floating: view.node_type == NodeType::FloatingCon
+            || matches!(view.floating, Some(Floating::AutoOn | Floating::UserOn)),
+        fullscreen: view.fullscreen_mode.is_some_and(|mode| mode > 0),
     }
+}
+
+/// Quotes a workspace name for a command. Markup contains quotes itself
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
 
+impl Autorename for Node {
     fn get_workspace_nodes(&self) -> Vec<&Node> {
         let mut nodes_to_search: Vec<&Node> = vec![self];
         let mut workspace_nodes = vec![];
@@ -51,169 +103,920 @@ impl Autorename for Node {
                 NodeType::Workspace => {
                     workspace_nodes.push(node);
                 }
+                // Reversed, so the workspaces are returned in tree order
                 _ => {
                     node.nodes
                         .iter()
+                        .rev()
                         .for_each(|child_node| nodes_to_search.push(child_node));
                 }
             }
         }
         workspace_nodes
     }
-    fn get_window_names(&self) -> Vec<String> {
+    fn get_views(&self) -> Vec<&Node> {
         let mut nodes_to_search: Vec<&Node> = vec![self];
-        let mut names = vec![];
+        let mut views = vec![];
         while let Some(node) = nodes_to_search.pop() {
-            if node.node_type == NodeType::Con {
-                // App_id on wayland
-                if let Some(name) = &node.app_id {
-                    names.push(name.clone());
-                } else {
-                    // Use the instance for xwayland applications
-                    let instance = node.window_properties.clone().and_then(|o| o.instance);
-                    if let Some(name) = instance {
-                        names.push(name);
-                    }
-                }
+            // Only views have an app_id (wayland) or window properties (xwayland)
+            if matches!(node.node_type, NodeType::Con | NodeType::FloatingCon)
+                && (node.app_id.is_some() || node.window_properties.is_some())
+            {
+                views.push(node);
             }
+            // Floating windows are listed after the tiled ones
             node.nodes
                 .iter()
+                .chain(node.floating_nodes.iter())
                 .for_each(|child_node| nodes_to_search.push(child_node));
         }
-        names
+        views
     }
 
-    async fn update_workspace_names(&self, name_config: &SwayNameManagerConfig) {
-        let mut nodes_to_search: Vec<&Node> = vec![self];
-        // Iterate over self including all children
-        while let Some(node) = nodes_to_search.pop() {
-            node.nodes.iter().for_each(|child_node| {
-                nodes_to_search.push(child_node);
-            });
-            // Build new name if we have a workspace. Scratchpad is ignored since it doesn' have a
-            // number
-            if node.node_type == NodeType::Workspace
-                && let Some(workspace_num) = node.num
-            {
-                // Get the window names and map them according to the config. If no match
-                // exists we use the id of the window
-                let window_names: Vec<String> = node
-                    .get_window_names()
-                    .iter()
-                    .map(|name| name_config.get_symbol(&[name]))
-                    .rev()
-                    .collect();
-                // Special case if the list is empty
-
-                let new_name = if window_names.is_empty() {
-                    format!("{workspace_num}")
-                } else {
-                    format!("{}: {}", workspace_num, window_names.join("|"))
-                };
-                let old_name = node.name.clone().unwrap_or_default();
-                // Only send the command if the new name differs
-                if new_name != old_name {
-                    let mut sway_connection = Connection::new().await.unwrap();
-                    let rename_commands =
-                        format!("rename workspace \"{old_name}\" to \"{new_name}\"",);
-                    sway_connection.run_command(rename_commands).await.unwrap();
-                }
+    fn get_windows(&self, initial_titles: &InitialTitles) -> Vec<WindowInfo> {
+        self.get_views()
+            .into_iter()
+            .map(|view| window_info(view, initial_titles))
+            .collect()
+    }
+
+    /// Maps every window below self to the number of its workspace
+    fn get_window_workspaces(&self) -> HashMap<i64, i32> {
+        self.get_workspace_nodes()
+            .into_iter()
+            .filter_map(|workspace| Some((workspace.num?, workspace)))
+            .flat_map(|(num, workspace)| {
+                workspace.get_views().into_iter().map(move |v| (v.id, num))
+            })
+            .collect()
+    }
+
+    fn get_new_name(
+        &self,
+        name_config: &SwayNameManagerConfig,
+        initial_titles: &InitialTitles,
+    ) -> Option<String> {
+        let num = self.num?;
+        // Get the window names and map them according to the config. Hidden windows are
+        // skipped
+        let window_names: Vec<String> = self
+            .get_views()
+            .into_iter()
+            .filter_map(|view| {
+                name_config.render_symbol(&window_info(view, initial_titles), window_state(view))
+            })
+            .rev()
+            .collect();
+        let current_name = self.name.clone().unwrap_or_default();
+        Some(naming::workspace_name(
+            name_config,
+            &num.to_string(),
+            &current_name,
+            &window_names,
+        ))
+    }
+
+    /// Whether self or any node below it is focused
+    fn contains_focus(&self) -> bool {
+        self.focused
+            || self
+                .nodes
+                .iter()
+                .chain(self.floating_nodes.iter())
+                .any(|node| node.contains_focus())
+    }
+
+    /// State of a workspace node. `None` for the scratchpad
+    fn get_state(
+        &self,
+        name_config: &SwayNameManagerConfig,
+        initial_titles: &InitialTitles,
+    ) -> Option<WorkspaceState> {
+        let mut windows = self.get_windows(initial_titles);
+        // Same order as the symbols
+        windows.reverse();
+        Some(WorkspaceState {
+            num: self.num?,
+            name: self.get_new_name(name_config, initial_titles)?,
+            focused: self.contains_focus(),
+            urgent: self.urgent,
+            fullscreen: self
+                .get_views()
+                .iter()
+                .any(|view| view.fullscreen_mode.is_some_and(|mode| mode > 0)),
+            windows,
+        })
+    }
+
+    /// Renames of all workspaces below self. Scratchpad is ignored since it doesn't have a number
+    fn get_renames(
+        &self,
+        name_config: &SwayNameManagerConfig,
+        initial_titles: &InitialTitles,
+    ) -> Vec<Rename<i64>> {
+        self.get_workspace_nodes()
+            .into_iter()
+            .filter_map(|workspace| {
+                Some(Rename {
+                    id: workspace.id,
+                    old: workspace.name.clone().unwrap_or_default(),
+                    new: workspace.get_new_name(name_config, initial_titles)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Sends the renames as a single command list. Self has to contain all workspaces, so
+    /// collisions with workspaces that are not renamed can be avoided
+    async fn rename_workspaces(
+        &self,
+        renames: Vec<Rename<i64>>,
+        connection: &mut Connection,
+    ) -> Fallible<()> {
+        let names: Vec<String> = self
+            .get_workspace_nodes()
+            .iter()
+            .filter_map(|workspace| workspace.name.clone())
+            .collect();
+        let commands: Vec<String> = rename::plan(renames, &names)
+            .iter()
+            .map(|r| format!("rename workspace {} to {}", quote(&r.old), quote(&r.new)))
+            .collect();
+        // Only send a command if any name differs
+        if commands.is_empty() {
+            return Ok(());
+        }
+        for (command, outcome) in commands
+            .iter()
+            .zip(connection.run_command(commands.join("; ")).await?)
+        {
+            if let Err(err) = outcome {
+                error!("{command} failed: {err}");
             }
         }
+        Ok(())
     }
 }
 
 impl WindowManager for SwayNameManager {
-    fn update_workspace(&self, id: i32, name: &str) -> anyhow::Result<()> {
-        // TODO: make everything async
-        futures::executor::block_on(async {
-            let mut connection = Connection::new().await.unwrap();
-            let workspaces = connection.get_workspaces().await.unwrap();
+    async fn update_workspace(&self, id: i32, name: &str) -> anyhow::Result<()> {
+        self.update_workspaces(&[(id, name.to_string())]).await
+    }
 
-            let workspace = workspaces
+    async fn update_workspaces(&self, names: &[(i32, String)]) -> anyhow::Result<()> {
+        self.request(async |connection| {
+            let root_node = connection.get_tree().await?;
+            let renames = names
                 .iter()
-                .find(|w| w.num == id)
-                .ok_or(anyhow!("not found"))
-                .unwrap();
-            let old_name = workspace.name.clone();
-            let rename_commands = format!("rename workspace \"{old_name}\" to \"{name}\"",);
-            connection.run_command(rename_commands).await.unwrap();
-        });
+                .map(|(id, name)| {
+                    let workspace = find_workspace(&root_node, *id)?;
+                    Ok(Rename {
+                        id: workspace.id,
+                        old: workspace.name.clone().unwrap_or_default(),
+                        new: name.clone(),
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?;
+            root_node.rename_workspaces(renames, connection).await?;
+            Ok(())
+        })
+        .await
+    }
 
-        Ok(())
+    async fn get_workspaces(&self) -> anyhow::Result<Vec<(i32, String)>> {
+        self.request(async |connection| {
+            let workspaces = connection.get_workspaces().await?;
+            Ok(workspaces.iter().map(|w| (w.num, w.name.clone())).collect())
+        })
+        .await
     }
 
-    fn get_workspaces(&self) -> anyhow::Result<Vec<i32>> {
-        let result = futures::executor::block_on(async {
-            let mut connection = Connection::new().await.unwrap();
-            let workspaces = connection.get_workspaces().await.unwrap();
-            workspaces.iter().map(|w| w.num).collect()
-        });
-        Ok(result)
-    }
-
-    fn get_workspace_name(&self, id: i32) -> anyhow::Result<String> {
-        let result = futures::executor::block_on(async {
-            let root_node = Connection::new().await.unwrap().get_tree().await.unwrap();
-            let mut nodes_to_search: Vec<&Node> = vec![&root_node];
-            // Iterate over self including all children
-            while let Some(node) = nodes_to_search.pop() {
-                node.nodes.iter().for_each(|child_node| {
-                    nodes_to_search.push(child_node);
-                });
-                // Build new name if we have a workspace. Scratchpad is ignored since it doesn' have a
-                // number
-                if node.node_type == NodeType::Workspace
-                    && let Some(workspace_node) = node.num
-                    && workspace_node == id
-                {
-                    // Get the window names and map them according to the config. If no match
-                    // exists we use the id of the window
-                    let window_names: Vec<String> = node
-                        .get_window_names()
-                        .iter()
-                        .map(|name| self.config.read().unwrap().get_symbol(&[name]))
-                        .rev()
-                        .collect();
-                    return window_names.join("|");
-                }
-            }
-            String::new()
-        });
-        Ok(result)
+    fn get_original_name(&self, id: i32) -> Option<String> {
+        self.tracker.original_name(id)
+    }
+
+    async fn get_workspace_name(&self, id: i32) -> anyhow::Result<String> {
+        let config = self.config.read().unwrap().clone();
+        let initial_titles = self.initial_titles.lock().unwrap().clone();
+        self.request(async |connection| {
+            let root_node = connection.get_tree().await?;
+            find_workspace(&root_node, id)?
+                .get_new_name(&config, &initial_titles)
+                .ok_or(anyhow!("Workspace {id} not found"))
+        })
+        .await
+    }
+
+    async fn update_all(&self) -> anyhow::Result<()> {
+        self.update_affected(Affected::all()).await
     }
+
+    /// Plans from a single tree like `update_all`
+    async fn plan_all(&self) -> anyhow::Result<Vec<Rename<i32>>> {
+        let config = self.config.read().unwrap().clone();
+        self.request(async |connection| {
+            let root_node = connection.get_tree().await?;
+            let initial_titles = self.record_initial_titles(&root_node);
+            let workspaces = root_node.get_workspace_nodes();
+            // Renames are reported by workspace number instead of container id
+            let renames = root_node
+                .get_renames(&config, &initial_titles)
+                .into_iter()
+                .filter_map(|r| {
+                    let workspace = workspaces.iter().find(|w| w.id == r.id)?;
+                    Some(Rename {
+                        id: workspace.num?,
+                        old: r.old,
+                        new: r.new,
+                    })
+                })
+                .collect();
+            let names: Vec<String> = workspaces.iter().filter_map(|w| w.name.clone()).collect();
+            Ok(rename::plan(renames, &names))
+        })
+        .await
+    }
+}
+
+/// Returns the workspace with the given number
+fn find_workspace(root_node: &Node, num: i32) -> anyhow::Result<&Node> {
+    root_node
+        .get_workspace_nodes()
+        .into_iter()
+        .find(|workspace| workspace.num == Some(num))
+        .ok_or(anyhow!("Workspace {num} not found"))
 }
 
 impl SwayNameManager {
-    pub async fn run(&mut self) -> Fallible<()> {
+    /// Updates the workspaces affected by events. The tree is fetched once
+    async fn update_affected(&self, affected: Affected<i64>) -> anyhow::Result<()> {
         let config = self.config.read().unwrap().clone();
-        let root_node = Connection::new().await?.get_tree().await?;
-        root_node.update_workspace_names(&config).await;
-        let subs = [EventType::Window];
-        let sway_connection = Connection::new().await?;
-        let mut events = sway_connection.subscribe(subs).await?;
-        while let Some(event) = events.next().await {
-            match event {
-                Ok(event) => {
-                    if let Event::Window(windowevent) = event {
-                        match windowevent.change {
-                            // TODO: On New we don't need to update all of them
-                            WindowChange::New | WindowChange::Close | WindowChange::Move => {
-                                let _ = self.update_all();
-                            }
-                            _ => {}
-                        }
+        self.request(async |connection| {
+            let root_node = connection.get_tree().await?;
+            let initial_titles = self.record_initial_titles(&root_node);
+            let workspaces: Vec<(i32, String, String)> = root_node
+                .get_workspace_nodes()
+                .into_iter()
+                .filter_map(|w| Some((w.num?, w.num?.to_string(), w.name.clone()?)))
+                .collect();
+            let names = self.tracker.update(
+                &config,
+                &affected,
+                &workspaces,
+                root_node.get_window_workspaces(),
+                |num| {
+                    find_workspace(&root_node, num)
+                        .ok()?
+                        .get_state(&config, &initial_titles)
+                },
+            );
+            // Numbers are not unique, so every workspace with a number is renamed on its own
+            let renames = root_node
+                .get_workspace_nodes()
+                .into_iter()
+                .filter(|w| names.iter().any(|(num, _)| w.num == Some(*num)))
+                .flat_map(|w| w.get_renames(&config, &initial_titles))
+                .collect();
+            root_node.rename_workspaces(renames, connection).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Remembers the titles of windows seen for the first time and forgets closed windows.
+    /// Windows opened before the start get the title they had then. Returns the initial titles
+    fn record_initial_titles(&self, root_node: &Node) -> InitialTitles {
+        let views = root_node.get_views();
+        let mut initial_titles = self.initial_titles.lock().unwrap();
+        initial_titles.retain(|id, _| views.iter().any(|view| view.id == *id));
+        for view in views {
+            if let Some(title) = title(view) {
+                initial_titles.entry(view.id).or_insert(title);
+            }
+        }
+        initial_titles.clone()
+    }
+
+    /// The workspaces an event changes. The title of new windows is recorded as their initial
+    /// title
+    fn affected_by(&self, event: Event) -> Affected<i64> {
+        match event {
+            Event::Window(windowevent) => {
+                if windowevent.change == WindowChange::New
+                    && let Some(title) = title(&windowevent.container)
+                {
+                    let id = windowevent.container.id;
+                    self.initial_titles.lock().unwrap().insert(id, title);
+                }
+                match windowevent.change {
+                    // New and Close only change the workspace of the window. Move also changes the
+                    // one it was on before
+                    WindowChange::New
+                    | WindowChange::Close
+                    | WindowChange::Move
+                    | WindowChange::Title
+                    | WindowChange::Floating
+                    | WindowChange::Urgent
+                    | WindowChange::FullscreenMode
+                    | WindowChange::Focus => Affected::window(windowevent.container.id),
+                    _ => Affected::default(),
+                }
+            }
+            Event::Workspace(workspaceevent) => match workspaceevent.change {
+                // Rename restores the icons if the user changed the label. Focus and Urgent only
+                // change the state
+                WorkspaceChange::Init
+                | WorkspaceChange::Move
+                | WorkspaceChange::Rename
+                | WorkspaceChange::Focus
+                | WorkspaceChange::Urgent => {
+                    match workspaceevent.current.and_then(|workspace| workspace.num) {
+                        Some(num) => Affected::workspace(num),
+                        None => Affected::default(),
                     }
                 }
+                // Empty workspaces are destroyed, so there is nothing left to rename
+                _ => Affected::default(),
+            },
+            _ => Affected::default(),
+        }
+    }
+
+    /// Runs and reconnects with a backoff whenever the connection is lost. All workspaces are
+    /// renamed after reconnecting. Returns once reconnecting failed too often
+    pub async fn run_forever(&self) -> anyhow::Result<()> {
+        if self.socket_path.is_none() {
+            return Err(anyhow!("The IPC socket is not set"));
+        }
+        reconnect("SwayNameManager", self).await
+    }
+
+    /// Runs `request` on the command connection. The connection is opened on first use and
+    /// dropped on errors, so the next request reconnects
+    async fn request<T>(
+        &self,
+        request: impl AsyncFnOnce(&mut Connection) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            *connection = Some(self.connect().await?);
+        }
+        let result = request(connection.as_mut().unwrap()).await;
+        if result.is_err() {
+            *connection = None;
+        }
+        result
+    }
+
+    async fn connect(&self) -> Fallible<Connection> {
+        let socket_path = self.socket_path.as_ref().ok_or(Error::SocketNotFound)?;
+        let stream = Async::<UnixStream>::connect(socket_path).await?;
+        Ok(Connection::from(stream))
+    }
+
+    /// Uses the socket given by SWAYSOCK
+    #[cfg(feature = "sway")]
+    pub fn new(
+        config: Arc<RwLock<SwayNameManagerConfig>>,
+        resync: watch::Receiver<()>,
+        output: Output,
+    ) -> Self {
+        Self::with_socket(
+            config,
+            resync,
+            output,
+            std::env::var_os("SWAYSOCK").map(PathBuf::from),
+        )
+    }
+
+    /// Renames all workspaces whenever `resync` changes
+    pub fn with_socket(
+        config: Arc<RwLock<SwayNameManagerConfig>>,
+        resync: watch::Receiver<()>,
+        output: Output,
+        socket_path: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            config,
+            socket_path,
+            connection: Mutex::new(None),
+            initial_titles: Default::default(),
+            resync,
+            tracker: Tracker::new(output),
+        }
+    }
+}
+
+impl Connected for SwayNameManager {
+    /// Runs until the connection to sway is lost. `backoff` is reset once subscribed
+    async fn run(&self, backoff: &mut Backoff) -> anyhow::Result<()> {
+        let subs = [EventType::Window, EventType::Workspace];
+        let sway_connection = self.connect().await?;
+        let events = sway_connection
+            .subscribe(subs)
+            .await?
+            .filter_map(async |event| match event {
+                Ok(event) => Some(Ok(self.affected_by(event))),
+                // The connection is broken, e.g. because sway exited. The stream would return
+                // this error forever
+                Err(err @ (Error::Io(_) | Error::InvalidMagic(_))) => Some(Err(err)),
                 Err(err) => {
                     error!("Error in event: {err}");
+                    None
                 }
+            });
+        backoff.reset();
+        // The command connection of a previous run most likely broke as well
+        *self.connection.lock().await = None;
+        let mut events = Debouncer::new(events, self.resync.clone(), self.config.clone());
+        if let Err(err) = self.update_all().await {
+            error!("Failed to update workspaces: {err}");
+        }
+        while let Some(affected) = events.next().await {
+            if let Err(err) = self.update_affected(affected?).await {
+                error!("Failed to update workspaces: {err}");
             }
         }
         Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::Restore;
+    use crate::wm::TEST_TIMEOUT;
+    use crate::wm::fake_sway::{FakeSway, node, tree, window, workspace};
+
+    fn manager(fake: &FakeSway, resync: watch::Receiver<()>, output: Output) -> SwayNameManager {
+        let config =
+            serde_json::from_value(json!({ "app_symbols": { "firefox": "F", "kitty": "K" } }));
+        SwayNameManager::with_socket(
+            Arc::new(RwLock::new(config.unwrap())),
+            resync,
+            output,
+            Some(fake.socket.clone()),
+        )
+    }
+
+    /// Returns the sender triggering a resync once the manager subscribed to the events and
+    /// computed the names of all workspaces
+    async fn spawn(fake: &FakeSway) -> watch::Sender<()> {
+        let (resync, receiver) = watch::channel(());
+        let output = Output::default();
+        let mut workspaces = output.workspaces.subscribe();
+        let manager = manager(fake, receiver, output);
+        tokio::spawn(async move { manager.run_forever().await });
+        tokio::time::timeout(TEST_TIMEOUT, workspaces.wait_for(|w| !w.is_empty()))
+            .await
+            .unwrap()
+            .unwrap();
+        resync
+    }
+
+    #[tokio::test]
+    async fn renames_on_start() {
+        let fake = FakeSway::start(tree(vec![
+            workspace(10, 1, "1", vec![window(11, "firefox", "Mozilla Firefox")]),
+            workspace(20, 2, "2: K", vec![window(21, "kitty", "zsh")]),
+            workspace(30, 3, "3", vec![]),
+        ]))
+        .await;
+        spawn(&fake).await;
+        assert_eq!(
+            fake.wait_for_commands(1).await,
+            [r#"rename workspace "1" to "1: F""#]
+        );
+        assert_eq!(fake.workspace_names(), ["1: F", "2: K", "3"]);
+    }
+
+    #[tokio::test]
+    async fn new_window_renames_its_workspace() {
+        let fake = FakeSway::start(tree(vec![
+            workspace(10, 1, "1", vec![]),
+            workspace(20, 2, "2", vec![]),
+        ]))
+        .await;
+        spawn(&fake).await;
+
+        let new_window = window(21, "kitty", "zsh");
+        fake.set_tree(tree(vec![
+            workspace(10, 1, "1", vec![]),
+            workspace(20, 2, "2", vec![new_window.clone()]),
+        ]));
+        fake.send_window_event("new", new_window);
+        assert_eq!(
+            fake.wait_for_commands(1).await,
+            [r#"rename workspace "2" to "2: K""#]
+        );
+    }
+
+    #[tokio::test]
+    async fn rules_match_the_initial_title() {
+        let fake = FakeSway::start(tree(vec![
+            workspace(10, 1, "1", vec![window(11, "kitty", "htop")]),
+            workspace(20, 2, "2", vec![]),
+        ]))
+        .await;
+        let config = serde_json::from_value(json!({
+            "rules": [
+                { "initial_title": "^Spotify$", "symbol": "S" },
+                { "initial_title": "^htop$", "symbol": "H" },
+            ],
+        }));
+        let output = Output::default();
+        let mut workspaces = output.workspaces.subscribe();
+        let manager = SwayNameManager::with_socket(
+            Arc::new(RwLock::new(config.unwrap())),
+            watch::channel(()).1,
+            output,
+            Some(fake.socket.clone()),
+        );
+        tokio::spawn(async move { manager.run_forever().await });
+        // Windows opened before the start keep the title they had then
+        workspaces.wait_for(|w| !w.is_empty()).await.unwrap();
+        assert_eq!(
+            fake.wait_for_commands(1).await,
+            [r#"rename workspace "1" to "1: H""#]
+        );
+
+        // The title changed before the tree was fetched
+        fake.set_tree(tree(vec![
+            workspace(10, 1, "1: H", vec![window(11, "kitty", "zsh")]),
+            workspace(20, 2, "2", vec![window(21, "spotify", "Song - Artist")]),
+        ]));
+        fake.send_window_event("new", window(21, "spotify", "Spotify"));
+        fake.send_window_event("title", window(11, "kitty", "zsh"));
+        assert_eq!(
+            fake.wait_for_commands(2).await[1..],
+            [r#"rename workspace "2" to "2: S""#]
+        );
+        let states = workspaces.borrow().clone();
+        assert_eq!(
+            states[&2].windows[0].initial_title.as_deref(),
+            Some("Spotify")
+        );
+    }
+
+    #[tokio::test]
+    async fn title_change_updates_only_its_workspace() {
+        let fake = FakeSway::start(tree(vec![
+            workspace(
+                10,
+                1,
+                "1: F",
+                vec![window(11, "firefox", "Mozilla Firefox")],
+            ),
+            workspace(20, 2, "2: K", vec![window(21, "kitty", "zsh")]),
+        ]))
+        .await;
+        spawn(&fake).await;
+
+        // Both workspaces are outdated, but only the one of the window should be renamed
+        fake.set_tree(tree(vec![
+            workspace(10, 1, "1", vec![window(11, "firefox", "Mozilla Firefox")]),
+            workspace(20, 2, "2", vec![window(21, "kitty", "vim")]),
+        ]));
+        fake.send_window_event("title", window(21, "kitty", "vim"));
+        assert_eq!(
+            fake.wait_for_commands(1).await,
+            [r#"rename workspace "2" to "2: K""#]
+        );
+    }
+
+    #[tokio::test]
+    async fn move_renames_source_and_destination() {
+        let fake = FakeSway::start(tree(vec![
+            workspace(10, 1, "1: K", vec![window(11, "kitty", "zsh")]),
+            workspace(20, 2, "2", vec![]),
+            workspace(30, 3, "3", vec![]),
+        ]))
+        .await;
+        let resync = spawn(&fake).await;
+
+        // Workspace 3 is outdated as well, but no event touched it
+        fake.set_tree(tree(vec![
+            workspace(10, 1, "1: K", vec![]),
+            workspace(20, 2, "2", vec![window(11, "kitty", "zsh")]),
+            workspace(30, 3, "3", vec![window(31, "firefox", "")]),
+        ]));
+        fake.send_window_event("move", window(11, "kitty", "zsh"));
+        assert_eq!(
+            fake.wait_for_commands(1).await,
+            [r#"rename workspace "1: K" to "1"; rename workspace "2" to "2: K""#]
+        );
+
+        // Closing the window renames the workspace it was on
+        fake.set_tree(tree(vec![
+            workspace(10, 1, "1", vec![]),
+            workspace(20, 2, "2: K", vec![]),
+            workspace(30, 3, "3", vec![window(31, "firefox", "")]),
+        ]));
+        fake.send_window_event("close", window(11, "kitty", "zsh"));
+        assert_eq!(
+            fake.wait_for_commands(2).await[1..],
+            [r#"rename workspace "2: K" to "2""#]
+        );
+
+        resync.send_replace(());
+        assert_eq!(
+            fake.wait_for_commands(3).await[2..],
+            [r#"rename workspace "3" to "3: F""#]
+        );
+    }
+
+    #[tokio::test]
+    async fn burst_is_renamed_once() {
+        let fake = FakeSway::start(tree(vec![
+            workspace(10, 1, "1", vec![]),
+            workspace(20, 2, "2", vec![]),
+        ]))
+        .await;
+        spawn(&fake).await;
+
+        let windows = [
+            window(11, "firefox", ""),
+            window(21, "kitty", ""),
+            window(22, "kitty", ""),
+        ];
+        fake.set_tree(tree(vec![
+            workspace(10, 1, "1", windows[..1].to_vec()),
+            workspace(20, 2, "2", windows[1..].to_vec()),
+        ]));
+        for window in windows {
+            fake.send_window_event("new", window);
+        }
+        assert_eq!(
+            fake.wait_for_commands(1).await,
+            [r#"rename workspace "1" to "1: F"; rename workspace "2" to "2: K|K""#]
+        );
+    }
+
+    #[tokio::test]
+    async fn user_rename_keeps_label() {
+        let fake = FakeSway::start(tree(vec![workspace(
+            10,
+            1,
+            "1: F",
+            vec![window(11, "firefox", "Mozilla Firefox")],
+        )]))
+        .await;
+        spawn(&fake).await;
+
+        let renamed = workspace(
+            10,
+            1,
+            "1:mail",
+            vec![window(11, "firefox", "Mozilla Firefox")],
+        );
+        fake.set_tree(tree(vec![renamed.clone()]));
+        fake.send_workspace_event("rename", renamed);
+        assert_eq!(
+            fake.wait_for_commands(1).await,
+            [r#"rename workspace "1:mail" to "1:mail: F""#]
+        );
+    }
+
+    #[tokio::test]
+    async fn reuses_connection() {
+        let fake = FakeSway::start(tree(vec![workspace(10, 1, "1", vec![])])).await;
+        spawn(&fake).await;
+
+        for (i, app_id) in ["kitty", "firefox", "kitty"].into_iter().enumerate() {
+            let new_window = window(11 + i as i64, app_id, "");
+            let mut tree = fake.tree();
+            tree["nodes"][0]["nodes"][0]["nodes"]
+                .as_array_mut()
+                .unwrap()
+                .push(new_window.clone());
+            fake.set_tree(tree);
+            fake.send_window_event("new", new_window);
+            fake.wait_for_commands(i + 1).await;
+        }
+        assert_eq!(fake.workspace_names(), ["1: K|F|K"]);
+        // One connection for commands and one for events
+        assert_eq!(fake.connections(), 2);
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_resyncs() {
+        let fake = FakeSway::start(tree(vec![workspace(10, 1, "1", vec![])])).await;
+        spawn(&fake).await;
+
+        // Changes while disconnected are picked up after reconnecting
+        fake.disconnect();
+        fake.set_tree(tree(vec![workspace(
+            10,
+            1,
+            "1",
+            vec![window(11, "kitty", "zsh")],
+        )]));
+        assert_eq!(
+            fake.wait_for_commands(1).await,
+            [r#"rename workspace "1" to "1: K""#]
+        );
+        // The events are received again
+        fake.set_tree(tree(vec![workspace(10, 1, "1: K", vec![])]));
+        fake.send_window_event("close", window(11, "kitty", "zsh"));
+        assert_eq!(
+            fake.wait_for_commands(2).await[1..],
+            [r#"rename workspace "1: K" to "1""#]
+        );
+    }
+
+    #[tokio::test]
+    async fn publishes_states_without_renaming() {
+        let mut focused = window(11, "firefox", "Mozilla Firefox");
+        focused["focused"] = json!(true);
+        let mut fullscreen = window(21, "kitty", "zsh");
+        fullscreen["fullscreen_mode"] = json!(1);
+        let fake = FakeSway::start(tree(vec![
+            workspace(10, 1, "1", vec![focused]),
+            workspace(20, 2, "2", vec![fullscreen.clone()]),
+        ]))
+        .await;
+        let output = Output::default();
+        output.paused.send_replace(true);
+        let mut workspaces = output.workspaces.subscribe();
+        let manager = manager(&fake, watch::channel(()).1, output);
+        tokio::spawn(async move { manager.run_forever().await });
+        let states = workspaces.wait_for(|w| w.len() == 2).await.unwrap().clone();
+        assert_eq!(states[&1].name, "1: F");
+        assert!(states[&1].focused);
+        assert!(!states[&2].focused && states[&2].fullscreen);
+
+        // Only the newly focused workspace is reported, but the other one loses the focus
+        let mut second = workspace(20, 2, "2", vec![fullscreen]);
+        second["focused"] = json!(true);
+        fake.set_tree(tree(vec![
+            workspace(10, 1, "1", vec![window(11, "firefox", "Mozilla Firefox")]),
+            second.clone(),
+        ]));
+        fake.send_workspace_event("focus", second);
+        let states = workspaces
+            .wait_for(|w| w[&2].focused)
+            .await
+            .unwrap()
+            .clone();
+        assert!(!states[&1].focused);
+        assert_eq!(fake.commands(), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn styles_focused_and_floating_windows() {
+        let mut focused = window(11, "firefox", "Mozilla Firefox");
+        focused["focused"] = json!(true);
+        let mut floating = window(12, "kitty", "zsh");
+        floating["type"] = json!("floating_con");
+        let mut workspace = workspace(10, 1, "1", vec![focused]);
+        workspace["floating_nodes"] = json!([floating]);
+        let fake = FakeSway::start(tree(vec![workspace])).await;
+        let config = serde_json::from_value(json!({
+            "app_symbols": { "firefox": "F", "kitty": "K" },
+            "styles": {
+                "focused": { "foreground": "white" },
+                "floating": { "font": "Sans Italic" },
+            },
+        }));
+        let manager = SwayNameManager::with_socket(
+            Arc::new(RwLock::new(config.unwrap())),
+            watch::channel(()).1,
+            Output::default(),
+            Some(fake.socket.clone()),
+        );
+        manager.update_all().await.unwrap();
+        assert_eq!(
+            fake.commands(),
+            [
+                r#"rename workspace "1" to "1: <span foreground=\"white\">F</span>|<span font=\"Sans Italic\">K</span>""#
+            ]
+        );
+        assert_eq!(
+            fake.workspace_names(),
+            [r#"1: <span foreground="white">F</span>|<span font="Sans Italic">K</span>"#]
+        );
+    }
+
+    #[tokio::test]
+    async fn styles_floating_windows_on_i3() {
+        let mut floating = window(12, "kitty", "zsh");
+        floating["floating"] = json!("user_on");
+        let mut workspace = workspace(10, 1, "1", vec![window(11, "firefox", "Mozilla Firefox")]);
+        workspace["floating_nodes"] = json!([node(13, "floating_con", None, vec![floating])]);
+        let fake = FakeSway::start(tree(vec![workspace])).await;
+        let config = serde_json::from_value(json!({
+            "app_symbols": { "firefox": "F", "kitty": "K" },
+            "styles": { "floating": { "font": "Sans Italic" } },
+        }));
+        let manager = SwayNameManager::with_socket(
+            Arc::new(RwLock::new(config.unwrap())),
+            watch::channel(()).1,
+            Output::default(),
+            Some(fake.socket.clone()),
+        );
+        manager.update_all().await.unwrap();
+        assert_eq!(
+            fake.workspace_names(),
+            [r#"1: F|<span font="Sans Italic">K</span>"#]
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_without_socket() {
+        let config = Arc::new(RwLock::new(SwayNameManagerConfig::default()));
+        let manager =
+            SwayNameManager::with_socket(config, watch::channel(()).1, Output::default(), None);
+        assert!(manager.run_forever().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn restores_names() {
+        let fake = FakeSway::start(tree(vec![
+            workspace(10, 1, "1:mail", vec![window(11, "firefox", "")]),
+            workspace(20, 2, "2", vec![window(21, "kitty", "")]),
+        ]))
+        .await;
+        let manager = manager(&fake, watch::channel(()).1, Output::default());
+        manager.update_all().await.unwrap();
+        assert_eq!(fake.workspace_names(), ["1:mail: F", "2: K"]);
+
+        manager.restore(Restore::Original).await.unwrap();
+        assert_eq!(fake.workspace_names(), ["1:mail", "2"]);
+        manager.restore(Restore::Number).await.unwrap();
+        assert_eq!(fake.workspace_names(), ["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn renames_chain_in_one_command() {
+        // Workspace 20 has to give up its name before workspace 10 can take it
+        let fake = FakeSway::start(tree(vec![
+            workspace(10, 1, "1", vec![window(11, "firefox", "a")]),
+            workspace(20, 1, "1: F", vec![window(21, "kitty", "b")]),
+        ]))
+        .await;
+        spawn(&fake).await;
+        assert_eq!(
+            fake.wait_for_commands(1).await,
+            [r#"rename workspace "1: F" to "1: K"; rename workspace "1" to "1: F""#]
+        );
+        assert_eq!(fake.workspace_names(), ["1: F", "1: K"]);
+    }
+
+    #[tokio::test]
+    async fn rename_workspaces_sends_one_command() {
+        let fake = FakeSway::start(tree(vec![
+            workspace(
+                10,
+                1,
+                "1",
+                vec![window(11, "firefox", "a"), window(12, "kitty", "b")],
+            ),
+            workspace(20, 2, "2", vec![window(21, "unknown", "c")]),
+        ]))
+        .await;
+        let manager = manager(&fake, watch::channel(()).1, Output::default());
+        let config = manager.config.read().unwrap().clone();
+        let mut connection = manager.connect().await.unwrap();
+        let root_node = connection.get_tree().await.unwrap();
+        let renames = root_node.get_renames(&config, &HashMap::new());
+        root_node
+            .rename_workspaces(renames, &mut connection)
+            .await
+            .unwrap();
+        assert_eq!(
+            fake.commands(),
+            [r#"rename workspace "1" to "1: F|K"; rename workspace "2" to "2: unknown""#]
+        );
+    }
 
-    pub fn new(config: Arc<RwLock<SwayNameManagerConfig>>) -> Self {
-        Self { config }
+    #[tokio::test]
+    async fn plan_all_fetches_the_tree_once() {
+        let fake = FakeSway::start(tree(vec![
+            workspace(10, 1, "1", vec![window(11, "firefox", "a")]),
+            workspace(20, 2, "2: K", vec![window(21, "kitty", "b")]),
+            workspace(30, 3, "3", vec![window(31, "kitty", "c")]),
+        ]))
+        .await;
+        let manager = manager(&fake, watch::channel(()).1, Output::default());
+        let renames = manager.plan_all().await.unwrap();
+        assert_eq!(
+            renames,
+            [
+                Rename {
+                    id: 1,
+                    old: "1".to_string(),
+                    new: "1: F".to_string(),
+                },
+                Rename {
+                    id: 3,
+                    old: "3".to_string(),
+                    new: "3: K".to_string(),
+                },
+            ]
+        );
+        assert_eq!(fake.tree_requests(), 1);
+        assert!(fake.commands().is_empty());
     }
 }